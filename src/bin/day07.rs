@@ -1,4 +1,7 @@
 use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+
+use num::{BigUint, One, Zero};
 
 use regex::Regex;
 
@@ -51,49 +54,379 @@ fn parse_input(input: &str) -> Vec<(String, Vec<(usize, String)>)> {
         .collect()
 }
 
-fn count_outermost(rules: &[(String, Vec<(usize, String)>)], bag: &str) -> usize {
-    let mut is_inside: HashMap<&str, Vec<&str>> = Default::default();
-    for (outer_bag, inner_bags) in rules {
-        for (_, inner_bag) in inner_bags {
-            is_inside.entry(inner_bag).or_default().push(outer_bag);
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct BagId(usize);
+
+#[derive(Debug, PartialEq, Eq)]
+enum GraphError {
+    UnknownBag(String),
+    Cycle(Vec<String>),
+}
+
+impl fmt::Display for GraphError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GraphError::UnknownBag(bag) => write!(f, "unknown bag: {}", bag),
+            GraphError::Cycle(bags) => write!(f, "bags contain each other: {}", bags.join(" -> ")),
         }
     }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Visit {
+    InProgress,
+    Done,
+}
+
+/// Chain of bags from `outer` down to the last step, each step with its count.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct ContainmentPath {
+    outer: BagId,
+    steps: Vec<(usize, BagId)>,
+}
+
+impl ContainmentPath {
+    /// How many of the innermost bags this path accounts for.
+    fn multiplicity(&self) -> BigUint {
+        self.steps
+            .iter()
+            .fold(BigUint::one(), |acc, (count, _)| acc * *count)
+    }
+}
 
-    let mut found = HashSet::new();
-    let mut queue = VecDeque::new();
-    queue.push_back(bag);
-    while let Some(elem) = queue.pop_front() {
-        if found.contains(elem) {
-            continue;
+#[derive(Debug, Default)]
+struct BagGraph {
+    names: Vec<String>,
+    ids: HashMap<String, BagId>,
+    contains: Vec<Vec<(usize, BagId)>>,
+    contained_in: Vec<Vec<(usize, BagId)>>,
+}
+
+impl BagGraph {
+    fn from_rules(rules: &[(String, Vec<(usize, String)>)]) -> Self {
+        let mut graph = Self::default();
+        for (outer_bag, inner_bags) in rules {
+            let outer = graph.intern(outer_bag);
+            for (count, inner_bag) in inner_bags {
+                let inner = graph.intern(inner_bag);
+                graph.contains[outer.0].push((*count, inner));
+                graph.contained_in[inner.0].push((*count, outer));
+            }
         }
-        found.insert(elem);
-        if let Some(inner_bags) = is_inside.get(elem) {
-            for bag in inner_bags {
-                queue.push_back(*bag);
+        graph
+    }
+
+    fn intern(&mut self, name: &str) -> BagId {
+        if let Some(id) = self.ids.get(name) {
+            return *id;
+        }
+        let id = BagId(self.names.len());
+        self.names.push(name.to_owned());
+        self.ids.insert(name.to_owned(), id);
+        self.contains.push(Vec::new());
+        self.contained_in.push(Vec::new());
+        id
+    }
+
+    fn id(&self, name: &str) -> Result<BagId, GraphError> {
+        self.ids
+            .get(name)
+            .copied()
+            .ok_or_else(|| GraphError::UnknownBag(name.to_owned()))
+    }
+
+    fn name(&self, id: BagId) -> &str {
+        &self.names[id.0]
+    }
+
+    fn cycle_error(&self, cycle: &[BagId]) -> GraphError {
+        GraphError::Cycle(cycle.iter().map(|id| self.name(*id).to_owned()).collect())
+    }
+
+    /// Depth-first search over `contains` starting at `start` without recursion.
+    /// Calls `on_done` for every bag once all of its contents are done.
+    /// Returns the offending path (first bag repeated at the end) on a cycle.
+    fn visit_post_order<F: FnMut(BagId)>(
+        &self,
+        start: BagId,
+        state: &mut HashMap<BagId, Visit>,
+        mut on_done: F,
+    ) -> Result<(), Vec<BagId>> {
+        if state.contains_key(&start) {
+            return Ok(());
+        }
+        state.insert(start, Visit::InProgress);
+        let mut stack = vec![(start, 0)];
+        while let Some((bag, next_child)) = stack.last_mut() {
+            let bag = *bag;
+            if let Some((_, child)) = self.contains[bag.0].get(*next_child) {
+                *next_child += 1;
+                match state.get(child) {
+                    Some(Visit::Done) => {}
+                    Some(Visit::InProgress) => {
+                        let from = stack.iter().position(|(b, _)| b == child).unwrap();
+                        let mut cycle: Vec<_> = stack[from..].iter().map(|(b, _)| *b).collect();
+                        cycle.push(*child);
+                        return Err(cycle);
+                    }
+                    None => {
+                        state.insert(*child, Visit::InProgress);
+                        stack.push((*child, 0));
+                    }
+                }
+            } else {
+                state.insert(bag, Visit::Done);
+                on_done(bag);
+                stack.pop();
             }
         }
+        Ok(())
+    }
+
+    fn find_cycle(&self) -> Option<Vec<BagId>> {
+        let mut state = HashMap::new();
+        (0..self.names.len())
+            .find_map(|idx| self.visit_post_order(BagId(idx), &mut state, |_| {}).err())
     }
-    found.len() - 1
-}
 
-fn count_inner(rules: &[(String, Vec<(usize, String)>)], bag: &str) -> usize {
-    rules
-        .iter()
-        .filter(|(rule_bag, _)| bag == rule_bag)
-        .map(|(_, contents)| {
-            contents
+    fn check_acyclic(&self) -> Result<(), GraphError> {
+        match self.find_cycle() {
+            Some(cycle) => Err(self.cycle_error(&cycle)),
+            None => Ok(()),
+        }
+    }
+
+    fn count_outermost(&self, bag: &str) -> Result<usize, GraphError> {
+        let start = self.id(bag)?;
+        let mut found = HashSet::new();
+        let mut queue = VecDeque::new();
+        queue.push_back(start);
+        while let Some(elem) = queue.pop_front() {
+            if !found.insert(elem) {
+                continue;
+            }
+            for (_, outer) in &self.contained_in[elem.0] {
+                queue.push_back(*outer);
+            }
+        }
+        Ok(found.len() - 1)
+    }
+
+    /// Number of bags `bag` amounts to, counting `bag` itself.
+    fn count_inner(&self, bag: &str) -> Result<BigUint, GraphError> {
+        let start = self.id(bag)?;
+        let mut totals: HashMap<BagId, BigUint> = HashMap::new();
+        let mut state = HashMap::new();
+        self.visit_post_order(start, &mut state, |bag| {
+            let total = self.contains[bag.0]
                 .iter()
-                .map(|(count, bag)| count_inner(rules, bag) * count)
-                .sum::<usize>()
-                + 1
+                .fold(BigUint::one(), |acc, (count, inner)| {
+                    acc + &totals[inner] * *count
+                });
+            totals.insert(bag, total);
         })
-        .sum()
+        .map_err(|cycle| self.cycle_error(&cycle))?;
+        Ok(totals
+            .remove(&start)
+            .expect("start should have been visited"))
+    }
+
+    /// Number of bags required inside `bag`, the puzzle's Part 2 answer.
+    fn count_required_inside(&self, bag: &str) -> Result<BigUint, GraphError> {
+        Ok(self.count_inner(bag)? - 1u32)
+    }
+
+    /// Bags from which `target` can be reached (including `target` itself).
+    fn containers_of(&self, target: BagId) -> HashSet<BagId> {
+        let mut found = HashSet::new();
+        let mut queue = VecDeque::new();
+        queue.push_back(target);
+        while let Some(elem) = queue.pop_front() {
+            if found.insert(elem) {
+                queue.extend(self.contained_in[elem.0].iter().map(|(_, outer)| *outer));
+            }
+        }
+        found
+    }
+
+    fn containment_paths(
+        &self,
+        outer: &str,
+        target: &str,
+    ) -> Result<Vec<ContainmentPath>, GraphError> {
+        let (outer, target) = (self.id(outer)?, self.id(target)?);
+        self.check_acyclic()?;
+        let relevant = self.containers_of(target);
+        let mut paths = Vec::new();
+        if outer != target && relevant.contains(&outer) {
+            let mut current = ContainmentPath {
+                outer,
+                steps: Vec::new(),
+            };
+            self.collect_paths(target, &relevant, &mut current, &mut paths);
+        }
+        Ok(paths)
+    }
+
+    fn collect_paths(
+        &self,
+        target: BagId,
+        relevant: &HashSet<BagId>,
+        current: &mut ContainmentPath,
+        paths: &mut Vec<ContainmentPath>,
+    ) {
+        let last = current.steps.last().map_or(current.outer, |(_, bag)| *bag);
+        for (count, inner) in &self.contains[last.0] {
+            if !relevant.contains(inner) {
+                continue;
+            }
+            current.steps.push((*count, *inner));
+            if *inner == target {
+                paths.push(current.clone());
+            } else {
+                self.collect_paths(target, relevant, current, paths);
+            }
+            current.steps.pop();
+        }
+    }
+
+    /// Path with the fewest nesting levels; works on cyclic rules as well.
+    fn shortest_containment_path(
+        &self,
+        outer: &str,
+        target: &str,
+    ) -> Result<Option<ContainmentPath>, GraphError> {
+        let (outer, target) = (self.id(outer)?, self.id(target)?);
+        let mut reached_from: HashMap<BagId, (usize, BagId)> = HashMap::new();
+        let mut queue = VecDeque::new();
+        queue.push_back(outer);
+        while let Some(elem) = queue.pop_front() {
+            for (count, inner) in &self.contains[elem.0] {
+                if *inner == outer || reached_from.contains_key(inner) {
+                    continue;
+                }
+                reached_from.insert(*inner, (*count, elem));
+                if *inner == target {
+                    let mut steps = Vec::new();
+                    let mut bag = target;
+                    while bag != outer {
+                        let (count, parent) = reached_from[&bag];
+                        steps.push((count, bag));
+                        bag = parent;
+                    }
+                    steps.reverse();
+                    return Ok(Some(ContainmentPath { outer, steps }));
+                }
+                queue.push_back(*inner);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Bags other than `target` itself that hold exactly `n` bags of type `target` at
+    /// any depth.
+    fn bags_containing_exactly(&self, n: usize, target: &str) -> Result<Vec<&str>, GraphError> {
+        let target = self.id(target)?;
+        let n = BigUint::from(n);
+        let mut counts: HashMap<BagId, BigUint> = HashMap::new();
+        let mut state = HashMap::new();
+        for idx in 0..self.names.len() {
+            self.visit_post_order(BagId(idx), &mut state, |bag| {
+                let count =
+                    self.contains[bag.0]
+                        .iter()
+                        .fold(BigUint::zero(), |acc, (count, inner)| {
+                            let nested = if *inner == target {
+                                &counts[inner] + 1u32
+                            } else {
+                                counts[inner].clone()
+                            };
+                            acc + nested * *count
+                        });
+                counts.insert(bag, count);
+            })
+            .map_err(|cycle| self.cycle_error(&cycle))?;
+        }
+        let mut bags: Vec<_> = counts
+            .into_iter()
+            .filter(|(bag, count)| *bag != target && *count == n)
+            .map(|(bag, _)| self.name(bag))
+            .collect();
+        bags.sort_unstable();
+        Ok(bags)
+    }
+
+    fn describe_path(&self, path: &ContainmentPath) -> String {
+        let mut description = self.name(path.outer).to_owned();
+        for (count, bag) in &path.steps {
+            description.push_str(&format!(" -{}-> {}", count, self.name(*bag)));
+        }
+        format!("{} (x{})", description, path.multiplicity())
+    }
+
+    fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph bags {\n");
+        for (idx, name) in self.names.iter().enumerate() {
+            dot.push_str(&format!("    {:?};\n", name));
+            for (count, inner) in &self.contains[idx] {
+                dot.push_str(&format!(
+                    "    {:?} -> {:?} [label={}];\n",
+                    name,
+                    self.name(*inner),
+                    count
+                ));
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
 }
 
 fn main() {
     let rules = parse_input(include_str!("../../inputs/day07.txt"));
-    println!("Part 1: {}", count_outermost(&rules, "shiny gold"));
-    println!("Part 1: {}", count_inner(&rules, "shiny gold") - 1);
+    let graph = BagGraph::from_rules(&rules);
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let query = match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["--dot"] => {
+            print!("{}", graph.to_dot());
+            return;
+        }
+        ["--paths", outer, target] => graph.containment_paths(outer, target).map(|paths| {
+            for path in paths {
+                println!("{}", graph.describe_path(&path));
+            }
+        }),
+        ["--shortest", outer, target] => {
+            graph
+                .shortest_containment_path(outer, target)
+                .map(|path| match path {
+                    Some(path) => println!("{}", graph.describe_path(&path)),
+                    None => println!("{} can't end up inside {}", target, outer),
+                })
+        }
+        ["--exactly", n, target] => {
+            let n = n.parse().unwrap_or_else(|_| {
+                eprintln!("usage: day07 --exactly <count> <bag>, got count `{}`", n);
+                std::process::exit(1);
+            });
+            graph.bags_containing_exactly(n, target).map(|bags| {
+                for bag in bags {
+                    println!("{}", bag);
+                }
+            })
+        }
+        _ => graph.check_acyclic().map(|_| {
+            println!("Part 1: {}", graph.count_outermost("shiny gold").unwrap());
+            println!(
+                "Part 2: {}",
+                graph.count_required_inside("shiny gold").unwrap()
+            );
+        }),
+    };
+    if let Err(err) = query {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
 }
 
 #[cfg(test)]
@@ -119,12 +452,130 @@ mod tests {
     #[test]
     fn test_part1_sample() {
         let rules = parse_input(include_str!("../../inputs/day07-sample.txt"));
-        assert_eq!(count_outermost(&rules, "shiny gold"), 4);
+        let graph = BagGraph::from_rules(&rules);
+        assert_eq!(graph.count_outermost("shiny gold"), Ok(4));
     }
 
     #[test]
     fn test_part2_sample() {
         let rules = parse_input(include_str!("../../inputs/day07-sample.txt"));
-        assert_eq!(count_inner(&rules, "shiny gold"), 33);
+        let graph = BagGraph::from_rules(&rules);
+        assert_eq!(graph.count_inner("shiny gold"), Ok(BigUint::from(33u32)));
+        assert_eq!(
+            graph.count_required_inside("shiny gold"),
+            Ok(BigUint::from(32u32))
+        );
+    }
+
+    #[test]
+    fn test_cycle_detection() {
+        let rules = parse_input(
+            "light red bags contain 1 bright white bag.
+bright white bags contain 2 shiny gold bags.
+shiny gold bags contain 3 light red bags.
+dim tan bags contain no other bags.",
+        );
+        let graph = BagGraph::from_rules(&rules);
+        let expected = GraphError::Cycle(vec![
+            "light red".to_owned(),
+            "bright white".to_owned(),
+            "shiny gold".to_owned(),
+            "light red".to_owned(),
+        ]);
+        assert_eq!(graph.check_acyclic(), Err(expected));
+        assert!(graph.count_inner("shiny gold").is_err());
+        assert_eq!(graph.count_inner("dim tan"), Ok(BigUint::from(1u32)));
+        assert_eq!(graph.count_outermost("shiny gold"), Ok(2));
+        assert_eq!(
+            graph.count_inner("muted yellow"),
+            Err(GraphError::UnknownBag("muted yellow".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_dot() {
+        let rules = parse_input(
+            "shiny gold bags contain 2 dark red bags.
+dark red bags contain no other bags.",
+        );
+        assert_eq!(
+            BagGraph::from_rules(&rules).to_dot(),
+            "digraph bags {
+    \"shiny gold\";
+    \"shiny gold\" -> \"dark red\" [label=2];
+    \"dark red\";
+}
+"
+        );
+    }
+
+    #[test]
+    fn test_containment_paths() {
+        let rules = parse_input(include_str!("../../inputs/day07-sample.txt"));
+        let graph = BagGraph::from_rules(&rules);
+        let paths = graph.containment_paths("shiny gold", "faded blue").unwrap();
+        let described: Vec<_> = paths.iter().map(|path| graph.describe_path(path)).collect();
+        assert_eq!(
+            described,
+            vec![
+                "shiny gold -1-> dark olive -3-> faded blue (x3)",
+                "shiny gold -2-> vibrant plum -5-> faded blue (x10)",
+            ]
+        );
+        let total: BigUint = paths.iter().map(ContainmentPath::multiplicity).sum();
+        assert_eq!(total, BigUint::from(13u32));
+        assert_eq!(
+            graph.containment_paths("faded blue", "shiny gold"),
+            Ok(vec![])
+        );
+    }
+
+    #[test]
+    fn test_shortest_containment_path() {
+        let rules = parse_input(include_str!("../../inputs/day07-sample.txt"));
+        let graph = BagGraph::from_rules(&rules);
+        let path = graph
+            .shortest_containment_path("light red", "faded blue")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            graph.describe_path(&path),
+            "light red -2-> muted yellow -9-> faded blue (x18)"
+        );
+        assert_eq!(
+            graph.shortest_containment_path("faded blue", "light red"),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn test_bags_containing_exactly() {
+        let rules = parse_input(include_str!("../../inputs/day07-sample.txt"));
+        let graph = BagGraph::from_rules(&rules);
+        assert_eq!(
+            graph.bags_containing_exactly(1, "shiny gold"),
+            Ok(vec!["bright white"])
+        );
+        assert_eq!(
+            graph.bags_containing_exactly(2, "shiny gold"),
+            Ok(vec!["muted yellow"])
+        );
+        assert_eq!(
+            graph.bags_containing_exactly(5, "shiny gold"),
+            Ok(vec!["light red"])
+        );
+        assert_eq!(
+            graph.bags_containing_exactly(13, "faded blue"),
+            Ok(vec!["bright white", "shiny gold"])
+        );
+        assert_eq!(
+            graph.bags_containing_exactly(0, "shiny gold"),
+            Ok(vec![
+                "dark olive",
+                "dotted black",
+                "faded blue",
+                "vibrant plum"
+            ])
+        );
     }
 }
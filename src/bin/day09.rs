@@ -1,91 +1,255 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::ops::Range;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Verdict {
+    Preamble,
+    /// numbers from the window that add up to the checked one
+    Valid(Vec<usize>),
+    Invalid,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Finding {
+    position: usize,
+    num: usize,
+    verdict: Verdict,
+}
+
+/// The summands have to come from the preamble window, at least one of them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct InvalidParams {
+    preamble: usize,
+    summands: usize,
+}
+
+impl fmt::Display for InvalidParams {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "can't pick {} summands from a preamble of {}, expected 1 to {}",
+            self.summands, self.preamble, self.preamble
+        )
+    }
+}
 
 #[derive(Clone, Debug)]
-struct StreamValidator {
-    len: usize,
-    num_buf: VecDeque<usize>,
-    sums_count: HashMap<usize, usize>,
+struct StreamAnalyzer {
+    preamble: usize,
+    summands: usize,
+    window: VecDeque<usize>,
+    position: usize,
 }
 
-impl StreamValidator {
-    fn new(len: usize) -> Self {
-        assert!(len > 0);
-        Self {
-            len,
-            num_buf: Default::default(),
-            sums_count: Default::default(),
+impl StreamAnalyzer {
+    fn new(preamble: usize, summands: usize) -> Result<Self, InvalidParams> {
+        if summands == 0 || summands > preamble {
+            return Err(InvalidParams { preamble, summands });
         }
+        Ok(Self {
+            preamble,
+            summands,
+            window: Default::default(),
+            position: 0,
+        })
     }
 
-    fn feed_num(&mut self, num: usize) -> bool {
-        // accept if there's not enough numbers or if there is a sum
-        let result = self.num_buf.len() < self.len
-            || self
-                .sums_count
-                .get(&num)
-                .map(|count| *count > 0)
-                .unwrap_or_default();
-
-        if self.num_buf.len() == self.len {
-            // time to drop a num from front
-            let dropped = self
-                .num_buf
-                .pop_front()
-                .expect("we already checked that there are some numbers here");
-            for other_num in self.num_buf.iter() {
-                if let Some(count) = self.sums_count.get_mut(&(dropped + other_num)) {
-                    *count -= 1;
-                }
+    fn feed(&mut self, num: usize) -> Finding {
+        let verdict = if self.window.len() < self.preamble {
+            Verdict::Preamble
+        } else {
+            match self.find_witness(num) {
+                Some(witness) => Verdict::Valid(witness),
+                None => Verdict::Invalid,
             }
-        }
+        };
+        let finding = Finding {
+            position: self.position,
+            num,
+            verdict,
+        };
 
-        // time to add the new number
-        assert!(self.num_buf.len() < self.len);
-        for other_num in self.num_buf.iter() {
-            *self.sums_count.entry(num + *other_num).or_default() += 1;
+        if self.window.len() == self.preamble {
+            self.window.pop_front();
         }
-        self.num_buf.push_back(num);
+        self.window.push_back(num);
+        self.position += 1;
 
-        result
+        finding
     }
-}
 
-fn find_invalid(nums: &[usize]) -> Option<usize> {
-    let mut validator = StreamValidator::new(25);
-    nums.iter().cloned().find(|num| !validator.feed_num(*num))
+    /// Finds `summands` numbers at distinct positions in the window adding up to `target`.
+    fn find_witness(&self, target: usize) -> Option<Vec<usize>> {
+        if self.summands == 2 {
+            let mut seen = HashSet::new();
+            return self.window.iter().find_map(|num| {
+                let other = target.checked_sub(*num)?;
+                if seen.contains(&other) {
+                    Some(vec![other, *num])
+                } else {
+                    seen.insert(*num);
+                    None
+                }
+            });
+        }
+        let mut sorted: Vec<usize> = self.window.iter().copied().collect();
+        sorted.sort_unstable();
+        let mut picked = Vec::with_capacity(self.summands);
+        if pick_summands(&sorted, self.summands, target, &mut picked) {
+            Some(picked)
+        } else {
+            None
+        }
+    }
 }
 
-fn find_contiguous_sum(nums: &[usize], target: usize) -> VecDeque<usize> {
-    let mut buf: VecDeque<usize> = Default::default();
-    let mut sum = 0;
-    for n in nums {
-        buf.push_back(*n);
-        sum += n;
-        if sum == target {
+/// Picks `count` numbers from ascending `nums` adding up to `target`.
+fn pick_summands(nums: &[usize], count: usize, target: usize, picked: &mut Vec<usize>) -> bool {
+    if count == 0 {
+        return target == 0;
+    }
+    for (idx, num) in nums.iter().enumerate() {
+        if *num > target {
             break;
         }
-        while sum > target {
-            if let Some(first) = buf.pop_front() {
-                sum -= first;
-            }
-            if sum == target {
-                return buf;
-            }
+        if idx > 0 && nums[idx - 1] == *num {
+            // same value was already tried at this level
+            continue;
         }
+        picked.push(*num);
+        if pick_summands(&nums[idx + 1..], count - 1, target - num, picked) {
+            return true;
+        }
+        picked.pop();
     }
-    buf
+    false
 }
 
-fn main() {
-    let nums: Vec<usize> = include_str!("../../inputs/day09.txt")
+/// Feeds every line of `reader` to `analyzer`.
+fn analyze<R: BufRead>(reader: R, mut analyzer: StreamAnalyzer) -> io::Result<Vec<Finding>> {
+    reader
         .lines()
-        .map(|line| line.parse::<usize>())
-        .collect::<Result<Vec<usize>, _>>()
-        .expect("nums should parse");
-    let invalid_num = find_invalid(&nums).expect("there should be a not accepted number");
+        .enumerate()
+        .map(|(idx, line)| {
+            let num = line?.trim().parse::<usize>().map_err(|err| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: {}", idx + 1, err),
+                )
+            })?;
+            Ok(analyzer.feed(num))
+        })
+        .collect()
+}
+
+fn find_invalid(findings: &[Finding]) -> impl Iterator<Item = &Finding> {
+    findings
+        .iter()
+        .filter(|finding| finding.verdict == Verdict::Invalid)
+}
+
+/// All ranges of at least `min_len` numbers adding up to `target`, ordered by start.
+fn contiguous_sums(nums: &[usize], target: usize, min_len: usize) -> Vec<Range<usize>> {
+    let min_len = min_len.max(1);
+    let mut prefix_sums = Vec::with_capacity(nums.len() + 1);
+    prefix_sums.push(0);
+    for num in nums {
+        prefix_sums.push(prefix_sums.last().unwrap() + num);
+    }
+
+    let mut starts_by_sum: HashMap<usize, Vec<usize>> = HashMap::new();
+    let mut ranges = Vec::new();
+    for end in min_len..prefix_sums.len() {
+        let start = end - min_len;
+        starts_by_sum
+            .entry(prefix_sums[start])
+            .or_default()
+            .push(start);
+        if let Some(starts) = prefix_sums[end]
+            .checked_sub(target)
+            .and_then(|sum| starts_by_sum.get(&sum))
+        {
+            ranges.extend(starts.iter().map(|start| *start..end));
+        }
+    }
+    ranges.sort_unstable_by_key(|range| (range.start, range.end));
+    ranges
+}
+
+fn find_contiguous_sum(nums: &[usize], target: usize) -> Option<Range<usize>> {
+    contiguous_sums(nums, target, 2).into_iter().next()
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}", message);
+    eprintln!("usage: day09 [preamble [summands]] [--input file|-] [--report]");
+    std::process::exit(1);
+}
+
+fn main() {
+    // usage: day09 [preamble [summands]] [--input file|-] [--report]
+    // reads the puzzle input unless a file (or `-` for stdin) is given
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let report = args.iter().any(|arg| arg == "--report");
+    let input_pos = args
+        .iter()
+        .position(|arg| arg == "--input")
+        .map(|pos| pos + 1);
+    let mut params = args
+        .iter()
+        .enumerate()
+        .filter(|(pos, arg)| !arg.starts_with("--") && Some(*pos) != input_pos)
+        .map(|(_, arg)| {
+            arg.parse::<usize>()
+                .unwrap_or_else(|_| usage_error(&format!("`{}` is not a count", arg)))
+        });
+    let preamble = params.next().unwrap_or(25);
+    let summands = params.next().unwrap_or(2);
+    let analyzer =
+        StreamAnalyzer::new(preamble, summands).unwrap_or_else(|err| usage_error(&err.to_string()));
+
+    let findings = match input_pos.map(|pos| args.get(pos).map(String::as_str)) {
+        None => analyze(include_str!("../../inputs/day09.txt").as_bytes(), analyzer),
+        Some(None) => usage_error("--input should name a file or `-`"),
+        Some(Some("-")) => analyze(io::stdin().lock(), analyzer),
+        Some(Some(path)) => {
+            File::open(path).and_then(|file| analyze(BufReader::new(file), analyzer))
+        }
+    }
+    .unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    });
+    if report {
+        for finding in &findings {
+            match &finding.verdict {
+                Verdict::Preamble => {}
+                Verdict::Valid(witness) => {
+                    let witness: Vec<_> = witness.iter().map(|num| num.to_string()).collect();
+                    println!(
+                        "#{} {} = {}",
+                        finding.position,
+                        finding.num,
+                        witness.join(" + ")
+                    );
+                }
+                Verdict::Invalid => println!("#{} {} is invalid", finding.position, finding.num),
+            }
+        }
+        return;
+    }
+
+    let nums: Vec<usize> = findings.iter().map(|finding| finding.num).collect();
+    let invalid_num = find_invalid(&findings)
+        .next()
+        .expect("there should be a not accepted number")
+        .num;
     println!("Part 1: {invalid_num}");
-    let contiguous_sum = find_contiguous_sum(&nums, invalid_num);
-    assert_eq!(contiguous_sum.iter().sum::<usize>(), invalid_num);
+    let range = find_contiguous_sum(&nums, invalid_num).expect("there should be a contiguous sum");
+    let contiguous_sum = &nums[range];
     println!(
         "Part 2: {}",
         contiguous_sum.iter().min().unwrap() + contiguous_sum.iter().max().unwrap()
@@ -102,6 +266,92 @@ mod tests {
             35, 20, 15, 25, 47, 40, 62, 55, 65, 95, 102, 117, 150, 182, 127, 219, 299, 277, 309,
             576,
         ];
-        assert_eq!(find_contiguous_sum(&nums, 127), vec![15, 25, 47, 40]);
+        assert_eq!(find_contiguous_sum(&nums, 127), Some(2..6));
+        assert_eq!(contiguous_sums(&nums, 127, 1), vec![2..6, 14..15]);
+        assert_eq!(find_contiguous_sum(&nums, 1), None);
+    }
+
+    #[test]
+    fn test_contiguous_sums_non_monotonic() {
+        let nums = vec![5, 1, 0, 4, 3, 2, 5, 0];
+        assert_eq!(
+            contiguous_sums(&nums, 5, 1),
+            vec![0..1, 1..4, 4..6, 6..7, 6..8]
+        );
+        assert_eq!(contiguous_sums(&nums, 5, 2), vec![1..4, 4..6, 6..8]);
+        assert_eq!(contiguous_sums(&nums, 5, 3), vec![1..4]);
+        assert_eq!(find_contiguous_sum(&[7, 5], 5), None);
+    }
+
+    const SAMPLE: &str = "35
+20
+15
+25
+47
+40
+62
+55
+65
+95
+102
+117
+150
+182
+127
+219
+299
+277
+309
+576";
+
+    #[test]
+    fn test_part1_sample() {
+        let findings = analyze(SAMPLE.as_bytes(), StreamAnalyzer::new(5, 2).unwrap()).unwrap();
+        let invalid: Vec<_> = find_invalid(&findings).collect();
+        assert_eq!(
+            invalid,
+            vec![&Finding {
+                position: 14,
+                num: 127,
+                verdict: Verdict::Invalid
+            }]
+        );
+        assert_eq!(findings[4].verdict, Verdict::Preamble);
+        assert_eq!(findings[5].verdict, Verdict::Valid(vec![15, 25]));
+    }
+
+    #[test]
+    fn test_three_summands() {
+        let analyzer = StreamAnalyzer::new(4, 3).unwrap();
+        let findings = analyze("1\n2\n3\n4\n6\n12\n99\n".as_bytes(), analyzer).unwrap();
+        let verdicts: Vec<_> = findings
+            .into_iter()
+            .map(|finding| finding.verdict)
+            .collect();
+        assert_eq!(
+            verdicts[4..],
+            [
+                Verdict::Valid(vec![1, 2, 3]),
+                Verdict::Valid(vec![2, 4, 6]),
+                Verdict::Invalid
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_params() {
+        assert_eq!(
+            StreamAnalyzer::new(25, 0).err(),
+            Some(InvalidParams {
+                preamble: 25,
+                summands: 0
+            })
+        );
+        let err = StreamAnalyzer::new(2, 5).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "can't pick 5 summands from a preamble of 2, expected 1 to 2"
+        );
+        assert!(StreamAnalyzer::new(2, 2).is_ok());
     }
 }
@@ -1,33 +1,161 @@
 use std::collections::VecDeque;
 
-fn solve_joltage(nums: &[usize]) -> [usize; 4] {
-    let mut result = [0usize; 4];
-    let mut nums = nums.to_vec();
-    nums.sort_unstable();
-    let mut last = 0;
-    for num in nums {
-        result[num - last] += 1;
-        last = num;
-    }
-    result
+use num::{BigUint, One, Zero};
+
+#[derive(Clone, Debug)]
+struct AdapterChain {
+    adapters: Vec<usize>,
+    tolerance: usize,
 }
 
-fn count_ways(nums: &[usize]) -> usize {
-    let mut nums = nums.to_vec();
-    nums.sort_unstable();
-    let mut ways = VecDeque::with_capacity(3);
-    ways.push_back((0, 1));
-    for n in nums {
-        while ways
-            .front()
-            .map(|(front, _)| *front + 3 < n)
-            .unwrap_or_default()
-        {
-            ways.pop_front();
+impl AdapterChain {
+    fn new(nums: &[usize], tolerance: usize) -> Self {
+        assert!(tolerance > 0);
+        let mut adapters = nums.to_vec();
+        adapters.sort_unstable();
+        Self {
+            adapters,
+            tolerance,
+        }
+    }
+
+    fn device_joltage(&self) -> usize {
+        self.adapters.last().copied().unwrap_or_default() + self.tolerance
+    }
+
+    /// Counts of joltage differences (index is the difference) between the outlet and the
+    /// adapters when every adapter is used. `None` if some gap is too big.
+    fn diff_histogram(&self) -> Option<Vec<usize>> {
+        let mut result = vec![0; self.tolerance + 1];
+        let mut last = 0;
+        for num in &self.adapters {
+            *result.get_mut(num - last)? += 1;
+            last = *num;
+        }
+        Some(result)
+    }
+
+    /// Like `diff_histogram` but also counts the final step up to the device.
+    fn diff_histogram_to_device(&self) -> Option<Vec<usize>> {
+        let mut result = self.diff_histogram()?;
+        let last = self.adapters.last().copied().unwrap_or_default();
+        result[self.device_joltage() - last] += 1;
+        Some(result)
+    }
+
+    fn count_arrangements(&self) -> BigUint {
+        let mut ways: VecDeque<(usize, BigUint)> = VecDeque::with_capacity(self.tolerance + 1);
+        ways.push_back((0, BigUint::one()));
+        for n in self.adapters.iter().copied() {
+            while ways
+                .front()
+                .map(|(front, _)| *front + self.tolerance < n)
+                .unwrap_or_default()
+            {
+                ways.pop_front();
+            }
+            let count = ways
+                .iter()
+                .filter(|(joltage, _)| *joltage < n)
+                .map(|(_, ways_count)| ways_count)
+                .sum();
+            ways.push_back((n, count));
+        }
+        // the device can only be reached from adapters within tolerance of it
+        let device = self.device_joltage();
+        ways.into_iter()
+            .filter(|(joltage, _)| joltage + self.tolerance >= device && *joltage < device)
+            .map(|(_, ways_count)| ways_count)
+            .fold(BigUint::zero(), |acc, ways_count| acc + ways_count)
+    }
+
+    /// Lazily enumerates every valid arrangement as a list of adapter joltages.
+    fn arrangements(&self) -> Arrangements<'_> {
+        let device = self.device_joltage();
+        let mut can_finish = vec![false; self.adapters.len()];
+        for idx in (0..self.adapters.len()).rev() {
+            let joltage = self.adapters[idx];
+            can_finish[idx] = joltage + self.tolerance >= device
+                || self
+                    .next_candidates(joltage, idx + 1)
+                    .any(|next| can_finish[next]);
+        }
+        let finishes_from_outlet =
+            self.tolerance >= device || self.next_candidates(0, 0).any(|next| can_finish[next]);
+        Arrangements {
+            chain: self,
+            can_finish,
+            stack: if finishes_from_outlet {
+                vec![(0, 0)]
+            } else {
+                vec![]
+            },
+            path: Vec::new(),
+        }
+    }
+
+    /// Indices from `start` onwards of adapters that fit after `joltage`.
+    fn next_candidates(&self, joltage: usize, start: usize) -> impl Iterator<Item = usize> + '_ {
+        (start..self.adapters.len())
+            .take_while(move |idx| self.adapters[*idx] <= joltage + self.tolerance)
+            .filter(move |idx| self.adapters[*idx] > joltage)
+    }
+
+    /// Fewest adapters that still connect the outlet to the device.
+    fn min_adapters(&self) -> Option<usize> {
+        let device = self.device_joltage();
+        let mut joltage = 0;
+        let mut used = 0;
+        let mut idx = 0;
+        while joltage + self.tolerance < device {
+            let next = self.next_candidates(joltage, idx).last()?;
+            joltage = self.adapters[next];
+            idx = next + 1;
+            used += 1;
+        }
+        Some(used)
+    }
+}
+
+struct Arrangements<'a> {
+    chain: &'a AdapterChain,
+    can_finish: Vec<bool>,
+    /// (joltage reached, index of the next adapter to try)
+    stack: Vec<(usize, usize)>,
+    /// adapters used to reach the top of the stack
+    path: Vec<usize>,
+}
+
+impl<'a> Iterator for Arrangements<'a> {
+    type Item = Vec<usize>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let device = self.chain.device_joltage();
+        let tolerance = self.chain.tolerance;
+        while let Some((joltage, next_idx)) = self.stack.last_mut() {
+            let joltage = *joltage;
+            let candidate = self
+                .chain
+                .next_candidates(joltage, *next_idx)
+                .find(|idx| self.can_finish[*idx]);
+            match candidate {
+                Some(idx) => {
+                    *next_idx = idx + 1;
+                    let adapter = self.chain.adapters[idx];
+                    self.path.push(adapter);
+                    self.stack.push((adapter, idx + 1));
+                    if adapter + tolerance >= device {
+                        return Some(self.path.clone());
+                    }
+                }
+                None => {
+                    self.stack.pop();
+                    self.path.pop();
+                }
+            }
         }
-        ways.push_back((n, ways.iter().map(|(_, ways_count)| *ways_count).sum()));
+        None
     }
-    ways.pop_back().map(|(_, ways)| ways).unwrap_or_default()
 }
 
 fn main() {
@@ -36,32 +164,115 @@ fn main() {
         .map(|line| line.parse::<usize>())
         .collect::<Result<Vec<usize>, _>>()
         .expect("nums should parse");
-    let diffs = solve_joltage(&nums);
-    println!("Part 1: {}", (diffs[3] + 1) * diffs[1]);
-    println!("Part 2: {}", count_ways(&nums));
+    // usage: day10 [tolerance] [--list count]
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let tolerance = match args.first() {
+        Some(arg) if !arg.starts_with("--") => arg.parse().expect("tolerance should be an int"),
+        _ => 3,
+    };
+    let chain = AdapterChain::new(&nums, tolerance);
+    if let Some(pos) = args.iter().position(|arg| arg == "--list") {
+        let count = args
+            .get(pos + 1)
+            .map_or(10, |arg| arg.parse().expect("count should be an int"));
+        for arrangement in chain.arrangements().take(count) {
+            let joltages: Vec<_> = arrangement.iter().map(|j| j.to_string()).collect();
+            println!("{}", joltages.join(" "));
+        }
+        return;
+    }
+    let diffs = chain
+        .diff_histogram_to_device()
+        .expect("all adapters should chain");
+    println!("Part 1: {}", diffs[tolerance] * diffs[1]);
+    println!("Part 2: {}", chain.count_arrangements());
+    println!(
+        "Minimum adapters: {}",
+        chain.min_adapters().expect("device should be reachable")
+    );
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    const SMALL_SAMPLE: [usize; 11] = [16, 10, 15, 5, 1, 11, 7, 19, 6, 12, 4];
+
     #[test]
     fn test_part1_samples() {
         assert_eq!(
-            solve_joltage(&[16, 10, 15, 5, 1, 11, 7, 19, 6, 12, 4]),
-            [0, 7, 0, 4]
+            AdapterChain::new(&SMALL_SAMPLE, 3).diff_histogram(),
+            Some(vec![0, 7, 0, 4])
         );
+        assert_eq!(
+            AdapterChain::new(&SMALL_SAMPLE, 3).diff_histogram_to_device(),
+            Some(vec![0, 7, 0, 5])
+        );
+        assert_eq!(AdapterChain::new(&[1, 5], 3).diff_histogram(), None);
     }
 
     #[test]
     fn test_part2_samples() {
-        assert_eq!(count_ways(&[16, 10, 15, 5, 1, 11, 7, 19, 6, 12, 4]), 8);
         assert_eq!(
-            count_ways(&[
-                28, 33, 18, 42, 31, 14, 46, 20, 48, 47, 24, 23, 49, 45, 19, 38, 39, 11, 1, 32, 25,
-                35, 8, 17, 7, 9, 4, 2, 34, 10, 3
-            ]),
-            19208
+            AdapterChain::new(&SMALL_SAMPLE, 3).count_arrangements(),
+            BigUint::from(8u32)
+        );
+        assert_eq!(
+            AdapterChain::new(
+                &[
+                    28, 33, 18, 42, 31, 14, 46, 20, 48, 47, 24, 23, 49, 45, 19, 38, 39, 11, 1, 32,
+                    25, 35, 8, 17, 7, 9, 4, 2, 34, 10, 3
+                ],
+                3
+            )
+            .count_arrangements(),
+            BigUint::from(19208u32)
+        );
+    }
+
+    #[test]
+    fn test_tolerance() {
+        let nums: Vec<usize> = (1..=10).collect();
+        assert_eq!(
+            AdapterChain::new(&nums, 1).count_arrangements(),
+            BigUint::one()
+        );
+        // compositions of 10 into parts of size 1 or 2
+        assert_eq!(
+            AdapterChain::new(&nums, 2).count_arrangements(),
+            BigUint::from(89u32)
+        );
+        // every subset of the first 9 adapters, the last one is required
+        assert_eq!(
+            AdapterChain::new(&nums, 10).count_arrangements(),
+            BigUint::from(512u32)
         );
     }
+
+    #[test]
+    fn test_long_chain_does_not_overflow() {
+        let nums: Vec<usize> = (1..=200).collect();
+        let count = AdapterChain::new(&nums, 3).count_arrangements();
+        assert!(count > BigUint::from(u64::MAX));
+    }
+
+    #[test]
+    fn test_arrangements() {
+        let chain = AdapterChain::new(&SMALL_SAMPLE, 3);
+        let arrangements: Vec<_> = chain.arrangements().collect();
+        assert_eq!(arrangements.len(), 8);
+        assert_eq!(arrangements[0], vec![1, 4, 5, 6, 7, 10, 11, 12, 15, 16, 19]);
+        assert_eq!(arrangements[7], vec![1, 4, 7, 10, 12, 15, 16, 19]);
+
+        let chain = AdapterChain::new(&[1, 2, 8], 3);
+        assert_eq!(chain.arrangements().next(), None);
+        assert_eq!(chain.count_arrangements(), BigUint::zero());
+    }
+
+    #[test]
+    fn test_min_adapters() {
+        assert_eq!(AdapterChain::new(&SMALL_SAMPLE, 3).min_adapters(), Some(8));
+        assert_eq!(AdapterChain::new(&[1, 2, 8], 3).min_adapters(), None);
+        assert_eq!(AdapterChain::new(&[], 3).min_adapters(), Some(0));
+    }
 }
@@ -1,3 +1,5 @@
+use std::fmt;
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 enum Facing {
     North,
@@ -27,6 +29,72 @@ impl Facing {
         }
         result
     }
+
+    fn after_turning_degrees(self, dir: Direction, degrees: isize) -> Result<Self, InstrError> {
+        Ok(self.after_turning_times(dir, quarter_turns(degrees)?))
+    }
+
+    /// Unit vector as (east, north).
+    fn unit(self) -> (isize, isize) {
+        match self {
+            Facing::North => (0, 1),
+            Facing::South => (0, -1),
+            Facing::East => (1, 0),
+            Facing::West => (-1, 0),
+        }
+    }
+}
+
+/// Number of 90 degree steps in a turn, the discrete ships can't turn by anything else.
+fn quarter_turns(degrees: isize) -> Result<usize, InstrError> {
+    if degrees % 90 != 0 {
+        return Err(InstrError::NotRightAngle(degrees));
+    }
+    Ok(degrees.rem_euclid(360) as usize / 90)
+}
+
+impl Direction {
+    /// Turning angle in degrees counter-clockwise.
+    fn signed(self, degrees: isize) -> isize {
+        match self {
+            Direction::Left => degrees,
+            Direction::Right => -degrees,
+        }
+    }
+}
+
+trait Navigate {
+    fn act(&mut self, instr: &(Action, isize)) -> Result<(), InstrError>;
+
+    /// Current position as (east, north).
+    fn position(&self) -> (f64, f64);
+
+    fn act_all(mut self, instructions: &[(Action, isize)]) -> Result<Self, InstrError>
+    where
+        Self: Sized,
+    {
+        for instr in instructions {
+            self.act(instr)?;
+        }
+        Ok(self)
+    }
+
+    /// Positions before the first and after every instruction.
+    fn record_path(
+        mut self,
+        instructions: &[(Action, isize)],
+    ) -> Result<Vec<(f64, f64)>, InstrError>
+    where
+        Self: Sized,
+    {
+        let mut path = Vec::with_capacity(instructions.len() + 1);
+        path.push(self.position());
+        for instr in instructions {
+            self.act(instr)?;
+            path.push(self.position());
+        }
+        Ok(path)
+    }
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -42,6 +110,20 @@ enum Action {
     Forward,
 }
 
+impl From<Action> for char {
+    fn from(action: Action) -> Self {
+        match action {
+            Action::Move(Facing::North) => 'N',
+            Action::Move(Facing::South) => 'S',
+            Action::Move(Facing::East) => 'E',
+            Action::Move(Facing::West) => 'W',
+            Action::Turn(Direction::Left) => 'L',
+            Action::Turn(Direction::Right) => 'R',
+            Action::Forward => 'F',
+        }
+    }
+}
+
 impl TryFrom<char> for Action {
     type Error = ();
 
@@ -76,24 +158,22 @@ impl Default for Ship {
     }
 }
 
-impl Ship {
-    fn act(&mut self, (action, value): &(Action, isize)) {
+impl Navigate for Ship {
+    fn act(&mut self, (action, value): &(Action, isize)) -> Result<(), InstrError> {
         match action {
             Action::Move(facing) => self.advance(*facing, *value),
-            Action::Turn(dir) => {
-                self.facing = self.facing.after_turning_times(*dir, *value as usize / 90)
-            }
+            Action::Turn(dir) => self.facing = self.facing.after_turning_degrees(*dir, *value)?,
             Action::Forward => self.advance(self.facing, *value),
         }
+        Ok(())
     }
 
-    fn act_all(mut self, instructions: &[(Action, isize)]) -> Self {
-        for instr in instructions {
-            self.act(instr);
-        }
-        self
+    fn position(&self) -> (f64, f64) {
+        (self.long as f64, self.lat as f64)
     }
+}
 
+impl Ship {
     fn advance(&mut self, facing: Facing, distance: isize) {
         match facing {
             Facing::North => self.lat += distance,
@@ -127,12 +207,12 @@ impl Default for ShipWithWaypoint {
     }
 }
 
-impl ShipWithWaypoint {
-    fn act(&mut self, (action, value): &(Action, isize)) {
+impl Navigate for ShipWithWaypoint {
+    fn act(&mut self, (action, value): &(Action, isize)) -> Result<(), InstrError> {
         match action {
             Action::Move(facing) => self.move_wp(*facing, *value),
             Action::Turn(dir) => {
-                for _ in 0..(*value / 90) {
+                for _ in 0..quarter_turns(*value)? {
                     self.rotate_wp(*dir)
                 }
             }
@@ -141,15 +221,15 @@ impl ShipWithWaypoint {
                 self.long += self.wp_long * *value;
             }
         }
+        Ok(())
     }
 
-    fn act_all(mut self, instructions: &[(Action, isize)]) -> Self {
-        for instr in instructions {
-            self.act(instr);
-        }
-        self
+    fn position(&self) -> (f64, f64) {
+        (self.long as f64, self.lat as f64)
     }
+}
 
+impl ShipWithWaypoint {
     fn move_wp(&mut self, facing: Facing, distance: isize) {
         match facing {
             Facing::North => self.wp_lat += distance,
@@ -179,33 +259,497 @@ impl ShipWithWaypoint {
     }
 }
 
-fn parse_input(input: &str) -> Option<Vec<(Action, isize)>> {
-    input
-        .lines()
-        .map(|line| {
-            let (first, rest) = line.split_at(1);
-            first
-                .chars()
-                .next()
-                .unwrap()
-                .try_into()
-                .ok()
-                .zip(rest.parse().ok())
-        })
-        .collect()
+/// Ship with a continuous heading, turns can be by any angle.
+#[derive(Clone, Copy, Debug, Default)]
+struct FreeShip {
+    /// degrees counter-clockwise from east
+    heading: f64,
+    lat: f64,
+    long: f64,
+}
+
+impl Navigate for FreeShip {
+    fn act(&mut self, (action, value): &(Action, isize)) -> Result<(), InstrError> {
+        match action {
+            Action::Move(facing) => {
+                let (east, north) = facing.unit();
+                self.long += (east * value) as f64;
+                self.lat += (north * value) as f64;
+            }
+            Action::Turn(dir) => {
+                self.heading = (self.heading + dir.signed(*value) as f64).rem_euclid(360.0)
+            }
+            Action::Forward => {
+                let (sin, cos) = self.heading.to_radians().sin_cos();
+                self.long += cos * *value as f64;
+                self.lat += sin * *value as f64;
+            }
+        }
+        Ok(())
+    }
+
+    fn position(&self) -> (f64, f64) {
+        (self.long, self.lat)
+    }
+}
+
+/// Waypoint ship whose waypoint can be rotated by any angle.
+#[derive(Clone, Copy, Debug)]
+struct FreeShipWithWaypoint {
+    lat: f64,
+    long: f64,
+    wp_lat: f64,
+    wp_long: f64,
+}
+
+impl Default for FreeShipWithWaypoint {
+    fn default() -> Self {
+        Self {
+            lat: 0.0,
+            long: 0.0,
+            wp_lat: 1.0,
+            wp_long: 10.0,
+        }
+    }
+}
+
+impl Navigate for FreeShipWithWaypoint {
+    fn act(&mut self, (action, value): &(Action, isize)) -> Result<(), InstrError> {
+        match action {
+            Action::Move(facing) => {
+                let (east, north) = facing.unit();
+                self.wp_long += (east * value) as f64;
+                self.wp_lat += (north * value) as f64;
+            }
+            Action::Turn(dir) => {
+                let (sin, cos) = (dir.signed(*value) as f64).to_radians().sin_cos();
+                let (wp_long, wp_lat) = (self.wp_long, self.wp_lat);
+                self.wp_long = wp_long * cos - wp_lat * sin;
+                self.wp_lat = wp_long * sin + wp_lat * cos;
+            }
+            Action::Forward => {
+                self.lat += self.wp_lat * *value as f64;
+                self.long += self.wp_long * *value as f64;
+            }
+        }
+        Ok(())
+    }
+
+    fn position(&self) -> (f64, f64) {
+        (self.long, self.lat)
+    }
+}
+
+fn path_to_csv(path: &[(f64, f64)]) -> String {
+    let mut csv = String::from("step,east,north\n");
+    for (step, (east, north)) in path.iter().enumerate() {
+        csv.push_str(&format!("{},{},{}\n", step, east, north));
+    }
+    csv
+}
+
+fn path_to_svg(path: &[(f64, f64)]) -> String {
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (0f64, 0f64, 0f64, 0f64);
+    // SVG's y axis points south
+    let points: Vec<(f64, f64)> = path
+        .iter()
+        .map(|(east, north)| (*east, 0.0 - north))
+        .collect();
+    for (x, y) in &points {
+        min_x = min_x.min(*x);
+        min_y = min_y.min(*y);
+        max_x = max_x.max(*x);
+        max_y = max_y.max(*y);
+    }
+    let margin = ((max_x - min_x).max(max_y - min_y) / 50.0).max(1.0);
+    let points: Vec<String> = points.iter().map(|(x, y)| format!("{},{}", x, y)).collect();
+    format!(
+        concat!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{} {} {} {}\">\n",
+            "  <polyline points=\"{}\" fill=\"none\" stroke=\"black\" ",
+            "stroke-width=\"1\" vector-effect=\"non-scaling-stroke\"/>\n",
+            "  <circle cx=\"0\" cy=\"0\" r=\"{}\" fill=\"red\"/>\n",
+            "</svg>\n"
+        ),
+        min_x - margin,
+        min_y - margin,
+        max_x - min_x + 2.0 * margin,
+        max_y - min_y + 2.0 * margin,
+        points.join(" "),
+        margin / 2.0,
+    )
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Turns {
+    /// only multiples of 90 degrees, as the discrete ships require
+    RightAngles,
+    AnyAngle,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum InstrError {
+    Empty,
+    UnknownAction(char),
+    InvalidValue(String),
+    NegativeValue(isize),
+    NotRightAngle(isize),
+}
+
+impl fmt::Display for InstrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InstrError::Empty => write!(f, "empty instruction"),
+            InstrError::UnknownAction(c) => write!(f, "unknown action {:?}", c),
+            InstrError::InvalidValue(value) => write!(f, "invalid value {:?}", value),
+            InstrError::NegativeValue(value) => write!(f, "negative value {}", value),
+            InstrError::NotRightAngle(value) => {
+                write!(f, "turn by {} degrees is not a multiple of 90", value)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct LineError {
+    /// 1-based line number
+    line: usize,
+    text: String,
+    error: InstrError,
+}
+
+impl fmt::Display for LineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {} ({:?}): {}", self.line, self.text, self.error)
+    }
+}
+
+fn parse_instr(line: &str, turns: Turns) -> Result<(Action, isize), InstrError> {
+    let mut chars = line.chars();
+    let first = chars.next().ok_or(InstrError::Empty)?;
+    let action = Action::try_from(first).map_err(|_| InstrError::UnknownAction(first))?;
+    let rest = chars.as_str();
+    let value: isize = rest
+        .parse()
+        .map_err(|_| InstrError::InvalidValue(rest.to_owned()))?;
+    if value < 0 {
+        return Err(InstrError::NegativeValue(value));
+    }
+    if matches!(action, Action::Turn(_)) && turns == Turns::RightAngles && value % 90 != 0 {
+        return Err(InstrError::NotRightAngle(value));
+    }
+    Ok((action, value))
+}
+
+/// Parses all lines, reporting every bad one.
+fn parse_input(input: &str, turns: Turns) -> Result<Vec<(Action, isize)>, Vec<LineError>> {
+    let mut instructions = Vec::new();
+    let mut errors = Vec::new();
+    for (idx, line) in input.lines().enumerate() {
+        match parse_instr(line.trim(), turns) {
+            Ok(instr) => instructions.push(instr),
+            Err(error) => errors.push(LineError {
+                line: idx + 1,
+                text: line.to_owned(),
+                error,
+            }),
+        }
+    }
+    if errors.is_empty() {
+        Ok(instructions)
+    } else {
+        Err(errors)
+    }
+}
+
+fn format_instr((action, value): &(Action, isize)) -> String {
+    format!("{}{}", char::from(*action), value)
+}
+
+/// Merges consecutive moves in the same direction and consecutive turns (mod 360),
+/// drops no-op instructions. Both ship models end up in the same place.
+fn normalize(instructions: &[(Action, isize)]) -> Vec<(Action, isize)> {
+    let mut result: Vec<(Action, isize)> = Vec::with_capacity(instructions.len());
+    // net counter-clockwise turn accumulated since the last non-turn instruction
+    let mut pending_turn = 0;
+    for (action, value) in instructions {
+        match action {
+            Action::Turn(dir) => pending_turn = (pending_turn + dir.signed(*value)).rem_euclid(360),
+            _ => {
+                push_turn(&mut result, pending_turn);
+                pending_turn = 0;
+                if *value == 0 {
+                    continue;
+                }
+                match result.last_mut() {
+                    Some((last_action, last_value)) if last_action == action => {
+                        *last_value += value
+                    }
+                    _ => result.push((*action, *value)),
+                }
+            }
+        }
+    }
+    push_turn(&mut result, pending_turn);
+    result
+}
+
+fn push_turn(result: &mut Vec<(Action, isize)>, degrees: isize) {
+    match degrees {
+        0 => {}
+        1..=180 => result.push((Action::Turn(Direction::Left), degrees)),
+        _ => result.push((Action::Turn(Direction::Right), 360 - degrees)),
+    }
+}
+
+fn or_exit<T>(result: Result<T, InstrError>) -> T {
+    result.unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    })
 }
 
 fn main() {
-    let instructions =
-        parse_input(include_str!("../../inputs/day12.txt")).expect("input should parse");
+    // usage: day12 [--continuous] [--waypoint] [--csv | --svg | --normalize]
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let has_flag = |flag: &str| args.iter().any(|arg| arg == flag);
+    let turns = if has_flag("--continuous") {
+        Turns::AnyAngle
+    } else {
+        Turns::RightAngles
+    };
+    let instructions = match parse_input(include_str!("../../inputs/day12.txt"), turns) {
+        Ok(instructions) => instructions,
+        Err(errors) => {
+            for error in errors {
+                eprintln!("{}", error);
+            }
+            std::process::exit(1);
+        }
+    };
+
+    if has_flag("--normalize") {
+        for instr in normalize(&instructions) {
+            println!("{}", format_instr(&instr));
+        }
+        return;
+    }
+
+    if has_flag("--csv") || has_flag("--svg") {
+        let path = or_exit(match (has_flag("--continuous"), has_flag("--waypoint")) {
+            (false, false) => Ship::default().record_path(&instructions),
+            (false, true) => ShipWithWaypoint::default().record_path(&instructions),
+            (true, false) => FreeShip::default().record_path(&instructions),
+            (true, true) => FreeShipWithWaypoint::default().record_path(&instructions),
+        });
+        if has_flag("--csv") {
+            print!("{}", path_to_csv(&path));
+        } else {
+            print!("{}", path_to_svg(&path));
+        }
+        return;
+    }
+
+    if has_flag("--continuous") {
+        let distance = |(east, north): (f64, f64)| east.abs() + north.abs();
+        println!(
+            "Part 1: {:.0}",
+            distance(or_exit(FreeShip::default().act_all(&instructions)).position())
+        );
+        println!(
+            "Part 2: {:.0}",
+            distance(or_exit(FreeShipWithWaypoint::default().act_all(&instructions)).position())
+        );
+        return;
+    }
+
     println!(
         "Part 1: {}",
-        Ship::default().act_all(&instructions).distance()
+        or_exit(Ship::default().act_all(&instructions)).distance()
     );
     println!(
         "Part 2: {}",
-        ShipWithWaypoint::default()
-            .act_all(&instructions)
-            .distance()
+        or_exit(ShipWithWaypoint::default().act_all(&instructions)).distance()
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = "F10
+N3
+F7
+R90
+F11";
+
+    fn assert_close(actual: (f64, f64), expected: (f64, f64)) {
+        assert!(
+            (actual.0 - expected.0).abs() < 1e-9 && (actual.1 - expected.1).abs() < 1e-9,
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_sample() {
+        let instructions = parse_input(SAMPLE, Turns::RightAngles).unwrap();
+        assert_eq!(
+            Ship::default().act_all(&instructions).unwrap().distance(),
+            25
+        );
+        assert_eq!(
+            ShipWithWaypoint::default()
+                .act_all(&instructions)
+                .unwrap()
+                .distance(),
+            286
+        );
+    }
+
+    #[test]
+    fn test_continuous_matches_discrete() {
+        let instructions =
+            parse_input(include_str!("../../inputs/day12.txt"), Turns::RightAngles).unwrap();
+        assert_close(
+            FreeShip::default()
+                .act_all(&instructions)
+                .unwrap()
+                .position(),
+            Ship::default().act_all(&instructions).unwrap().position(),
+        );
+        let expected = ShipWithWaypoint::default()
+            .act_all(&instructions)
+            .unwrap()
+            .position();
+        let actual = FreeShipWithWaypoint::default()
+            .act_all(&instructions)
+            .unwrap()
+            .position();
+        assert!((actual.0 - expected.0).abs() < 1e-6 && (actual.1 - expected.1).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_arbitrary_angles() {
+        let instructions = parse_input("L45\nF10\nR135\nF10", Turns::AnyAngle).unwrap();
+        let half = 10.0 * std::f64::consts::FRAC_1_SQRT_2;
+        assert_close(
+            FreeShip::default()
+                .act_all(&instructions)
+                .unwrap()
+                .position(),
+            (half, half - 10.0),
+        );
+        let instructions = parse_input("R30\nF2", Turns::AnyAngle).unwrap();
+        let (sin, cos) = (-30f64).to_radians().sin_cos();
+        assert_close(
+            FreeShipWithWaypoint::default()
+                .act_all(&instructions)
+                .unwrap()
+                .position(),
+            (2.0 * (10.0 * cos - sin), 2.0 * (10.0 * sin + cos)),
+        );
+    }
+
+    #[test]
+    fn test_discrete_rejects_odd_angles() {
+        let instructions = parse_input("F1\nR37", Turns::AnyAngle).unwrap();
+        assert_eq!(
+            Ship::default().act_all(&instructions).err(),
+            Some(InstrError::NotRightAngle(37))
+        );
+        assert_eq!(
+            ShipWithWaypoint::default().record_path(&instructions).err(),
+            Some(InstrError::NotRightAngle(37))
+        );
+    }
+
+    #[test]
+    fn test_record_path() {
+        let instructions = parse_input(SAMPLE, Turns::RightAngles).unwrap();
+        let path = Ship::default().record_path(&instructions).unwrap();
+        assert_eq!(
+            path,
+            vec![
+                (0.0, 0.0),
+                (10.0, 0.0),
+                (10.0, 3.0),
+                (17.0, 3.0),
+                (17.0, 3.0),
+                (17.0, -8.0)
+            ]
+        );
+        assert_eq!(
+            path_to_csv(&path[..3]),
+            "step,east,north\n0,0,0\n1,10,0\n2,10,3\n"
+        );
+        let svg = path_to_svg(&path);
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains("points=\"0,0 10,0 10,-3 17,-3 17,-3 17,8\""));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let errors = parse_input("F10\nR37\n\nX5\nN\nS-3\nL270", Turns::RightAngles).unwrap_err();
+        let messages: Vec<_> = errors.iter().map(|error| error.to_string()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "line 2 (\"R37\"): turn by 37 degrees is not a multiple of 90",
+                "line 3 (\"\"): empty instruction",
+                "line 4 (\"X5\"): unknown action 'X'",
+                "line 5 (\"N\"): invalid value \"\"",
+                "line 6 (\"S-3\"): negative value -3",
+            ]
+        );
+        assert_eq!(
+            parse_input("R37", Turns::AnyAngle),
+            Ok(vec![(Action::Turn(Direction::Right), 37)])
+        );
+    }
+
+    #[test]
+    fn test_normalize() {
+        let instructions = parse_input(
+            "N3\nN4\nF1\nF0\nF2\nR90\nR180\nL90\nE0\nS1\nL90\nR90\nS2\nR270\nL450",
+            Turns::RightAngles,
+        )
+        .unwrap();
+        let normalized: Vec<_> = normalize(&instructions).iter().map(format_instr).collect();
+        assert_eq!(normalized, vec!["N7", "F3", "L180", "S3", "L180"]);
+    }
+
+    #[test]
+    fn test_normalize_keeps_positions() {
+        for input in [
+            SAMPLE,
+            "N3\nS5\nE2\nW2\nF4\nL90\nF1",
+            "R90\nL90\nF3\nR270\nR90\nF2\nN0",
+            "L180\nR360\nF1\nE4\nW1\nL90\nL90\nF2\nS3",
+            "F0\nR90\nN2\nL90\nF1\nR180\nF5\nW3\nE3",
+        ] {
+            let instructions = parse_input(input, Turns::RightAngles).unwrap();
+            let normalized = normalize(&instructions);
+            assert!(normalized.len() <= instructions.len());
+            assert_eq!(
+                Ship::default().act_all(&normalized).unwrap().position(),
+                Ship::default().act_all(&instructions).unwrap().position(),
+                "{}",
+                input
+            );
+            assert_eq!(
+                ShipWithWaypoint::default()
+                    .act_all(&normalized)
+                    .unwrap()
+                    .position(),
+                ShipWithWaypoint::default()
+                    .act_all(&instructions)
+                    .unwrap()
+                    .position(),
+                "{}",
+                input
+            );
+        }
+    }
+}
@@ -1,48 +1,210 @@
-use num::integer::lcm;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use advent_of_code_2020::crt::{self, Congruence, Overflow};
+use num::Integer;
+
+/// Bus ids with their offsets in the schedule, `x` entries are skipped. `Err` with the
+/// offset of a bus with id 0, which never departs.
+fn parse_schedule(raw: &str) -> Result<Vec<(usize, usize)>, usize> {
+    raw.split(',')
+        .enumerate()
+        .map(|(offset, s)| (offset, s.parse::<usize>()))
+        .filter(|(_, b)| Result::is_ok(b))
+        .map(|(offset, b)| (offset, b.expect("errors should have been filtered out")))
+        .map(|(offset, bus)| {
+            if bus == 0 {
+                Err(offset)
+            } else {
+                Ok((offset, bus))
+            }
+        })
+        .collect()
+}
 
 fn find_earliest_time_and_bus(buses: &[usize], time: usize) -> (usize, usize) {
     buses
         .iter()
-        .map(|b| {
-            (
-                if time % *b == 0 {
-                    time
-                } else {
-                    time + *b - (time % *b)
-                },
-                *b,
-            )
-        })
+        .map(|b| (time.next_multiple_of(*b), *b))
         .min()
         .expect("there should be at least one")
 }
 
-fn find_sync_timestamp(offsetted_buses: &[(usize, usize)]) -> usize {
-    let mut t = 0;
-    let mut period = 1;
-    for (offset, bus_id) in offsetted_buses {
-        while (t + *offset) % *bus_id != 0 {
-            t += period;
+/// Earliest non-negative `t` such that each bus departs at `t + offset`, offsets can be
+/// negative or larger than the bus id. `Ok(None)` if the buses never line up.
+fn find_sync_timestamp(offsetted_buses: &[(i128, i128)]) -> Result<Option<i128>, Overflow> {
+    let solution = crt::solve(
+        offsetted_buses
+            .iter()
+            .map(|(offset, bus_id)| Congruence::new(-offset, *bus_id)),
+    )?;
+    Ok(solution.map(|solution| solution.residue))
+}
+
+/// Next `count` departures at or after `time` across all buses, as (time, bus).
+fn next_departures(buses: &[usize], time: usize, count: usize) -> Vec<(usize, usize)> {
+    let mut queue: BinaryHeap<_> = buses
+        .iter()
+        .map(|bus| Reverse((time.next_multiple_of(*bus), *bus)))
+        .collect();
+    let mut departures = Vec::with_capacity(count);
+    while departures.len() < count {
+        let Some(Reverse((departure, bus))) = queue.pop() else {
+            break;
+        };
+        departures.push((departure, bus));
+        queue.push(Reverse((departure + bus, bus)));
+    }
+    departures
+}
+
+/// Earliest `t >= time` such that every bus departs somewhere in `t..=t + window`.
+///
+/// Buses with few departure offsets that fit the window are pinned down with the CRT,
+/// one branch per offset, and the remaining ones are checked by sweeping through the
+/// solutions of the pinned ones. Each bus costs the smaller of the two factors, so at
+/// most the square root of its id. The sweep stops after one period of all the buses,
+/// `Err` if that period doesn't fit into an `i128`.
+fn earliest_window(buses: &[usize], window: usize, time: usize) -> Result<Option<usize>, Overflow> {
+    fn search(
+        pinned: &[i128],
+        swept: &[i128],
+        window: i128,
+        time: i128,
+        acc: Congruence<i128>,
+        best: &mut Option<i128>,
+    ) -> Result<(), Overflow> {
+        if best.is_some_and(|best| acc.first_at_least(&time) >= best) {
+            return Ok(());
+        }
+        let Some((bus, rest)) = pinned.split_first() else {
+            return sweep(swept, window, time, &acc, best);
+        };
+        for offset in 0..=window {
+            let congruence = Congruence::new(-offset, *bus);
+            if let Some(acc) = acc.merge(&congruence)? {
+                search(rest, swept, window, time, acc, best)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn sweep(
+        swept: &[i128],
+        window: i128,
+        time: i128,
+        acc: &Congruence<i128>,
+        best: &mut Option<i128>,
+    ) -> Result<(), Overflow> {
+        // the departures of the swept buses repeat after `period`
+        let period = swept
+            .iter()
+            .try_fold(acc.modulus, |period, bus| {
+                (period / period.gcd(bus)).checked_mul(*bus)
+            })
+            .ok_or(Overflow)?;
+        let mut t = acc.first_at_least(&time);
+        let end = t.checked_add(period).ok_or(Overflow)?;
+        while t < end && best.is_none_or(|best| t < best) {
+            if swept.iter().all(|bus| (-t).rem_euclid(*bus) <= window) {
+                *best = Some(t);
+                break;
+            }
+            t += acc.modulus;
         }
-        period = lcm(period, *bus_id);
+        Ok(())
     }
-    t
+
+    let window = window as i128;
+    let mut buses: Vec<i128> = buses
+        .iter()
+        .map(|bus| *bus as i128)
+        // any `bus` consecutive minutes contain a departure
+        .filter(|bus| window < bus - 1)
+        .collect();
+    buses.sort_unstable_by(|a, b| b.cmp(a));
+    buses.dedup();
+    let (pinned, swept): (Vec<i128>, Vec<i128>) = buses
+        .iter()
+        .partition(|bus| (window + 1) * (window + 1) <= **bus);
+    let mut best = None;
+    search(
+        &pinned,
+        &swept,
+        window,
+        time as i128,
+        Congruence::any(),
+        &mut best,
+    )?;
+    Ok(best.map(|t| t as usize))
+}
+
+/// Next time at or after `time` when buses `a` and `b` depart together.
+fn next_together(a: usize, b: usize, time: usize) -> Result<usize, Overflow> {
+    earliest_window(&[a, b], 0, time).map(|t| t.expect("departing together is always possible"))
+}
+
+fn or_exit<T>(result: Result<T, Overflow>) -> T {
+    result.unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    })
 }
 
 fn main() {
     let time = 1000677;
     let buses_raw = "29,x,x,x,x,x,x,x,x,x,x,x,x,x,x,x,x,x,x,41,x,x,x,x,x,x,x,x,x,661,x,x,x,x,x,x,x,x,x,x,x,x,13,17,x,x,x,x,x,x,x,x,23,x,x,x,x,x,x,x,521,x,x,x,x,x,37,x,x,x,x,x,x,x,x,x,x,x,x,19";
-    let offsetted_buses: Vec<(usize, usize)> = buses_raw
-        .split(',')
-        .enumerate()
-        .map(|(offset, s)| (offset, s.parse::<usize>()))
-        .filter(|(_, b)| Result::is_ok(b))
-        .map(|(offset, b)| (offset, b.expect("errors should have been filtered out")))
-        .collect::<Vec<_>>();
+    let offsetted_buses = parse_schedule(buses_raw).unwrap_or_else(|offset| {
+        eprintln!("bus at offset {} has id 0", offset);
+        std::process::exit(1);
+    });
     let buses: Vec<usize> = offsetted_buses.iter().map(|(_, bus)| *bus).collect();
+
+    // usage: day13 [--next count | --window width bus,bus,... | --together bus bus]
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let parse_num = |arg: &str| arg.parse::<usize>().expect("argument should be an int");
+    let parse_bus = |arg: &str| match parse_num(arg) {
+        0 => {
+            eprintln!("bus ids should be positive");
+            std::process::exit(1);
+        }
+        bus => bus,
+    };
+    match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["--next", count] => {
+            for (departure, bus) in next_departures(&buses, time, parse_num(count)) {
+                println!("{} bus {}", departure, bus);
+            }
+            return;
+        }
+        ["--window", window, subset] => {
+            let subset: Vec<_> = subset.split(',').map(parse_bus).collect();
+            match or_exit(earliest_window(&subset, parse_num(window), time)) {
+                Some(t) => println!("{}", t),
+                None => println!("buses never depart within {} minutes", window),
+            }
+            return;
+        }
+        ["--together", a, b] => {
+            println!(
+                "{}",
+                or_exit(next_together(parse_bus(a), parse_bus(b), time))
+            );
+            return;
+        }
+        _ => {}
+    }
+
     let (earliest_time, earliest_bus) = find_earliest_time_and_bus(&buses, time);
     println!("Part 1: {}", (earliest_time - time) * earliest_bus);
-    println!("Part 2: {}", find_sync_timestamp(&offsetted_buses));
+    let offsetted_buses: Vec<(i128, i128)> = offsetted_buses
+        .iter()
+        .map(|(offset, bus_id)| (*offset as i128, *bus_id as i128))
+        .collect();
+    println!(
+        "Part 2: {}",
+        or_exit(find_sync_timestamp(&offsetted_buses)).expect("buses should line up")
+    );
 }
 
 #[cfg(test)]
@@ -51,6 +213,83 @@ mod tests {
 
     #[test]
     fn test_samples() {
-        assert_eq!(find_sync_timestamp(&[(0, 17), (2, 13), (3, 19)]), 3417);
+        assert_eq!(
+            find_sync_timestamp(&[(0, 17), (2, 13), (3, 19)]),
+            Ok(Some(3417))
+        );
+        assert_eq!(
+            find_sync_timestamp(&[(0, 1789), (1, 37), (2, 47), (3, 1889)]),
+            Ok(Some(1202161486))
+        );
+    }
+
+    #[test]
+    fn test_generalized() {
+        // offsets larger than the id and negative ones
+        assert_eq!(
+            find_sync_timestamp(&[(0, 17), (15, 13), (-16, 19)]),
+            Ok(Some(3417))
+        );
+        // non-coprime ids
+        assert_eq!(find_sync_timestamp(&[(0, 4), (2, 6)]), Ok(Some(4)));
+        assert_eq!(find_sync_timestamp(&[(0, 4), (1, 6)]), Ok(None));
+        // solvable, but the combined period doesn't fit into an i128
+        let big = i64::MAX as i128;
+        assert_eq!(
+            find_sync_timestamp(&[(0, big), (1, big - 1), (2, big - 2)]),
+            Err(Overflow)
+        );
+    }
+
+    #[test]
+    fn test_planner() {
+        assert_eq!(parse_schedule("7,x,0,13"), Err(2));
+        let buses: Vec<_> = parse_schedule("7,13,x,x,59,x,31,19")
+            .unwrap()
+            .into_iter()
+            .map(|(_, bus)| bus)
+            .collect();
+        assert_eq!(
+            next_departures(&buses, 939, 4),
+            vec![(944, 59), (945, 7), (949, 13), (950, 19)]
+        );
+        assert_eq!(next_together(7, 13, 939), Ok(1001));
+        assert_eq!(next_together(4, 6, 13), Ok(24));
+        assert_eq!(earliest_window(&[7, 13], 1, 0), Ok(Some(0)));
+        assert_eq!(earliest_window(&[7, 13], 1, 1), Ok(Some(13)));
+        assert_eq!(earliest_window(&[4, 6], 0, 1), Ok(Some(12)));
+        assert_eq!(earliest_window(&[4, 8], 2, 1), Ok(Some(6)));
+        assert_eq!(earliest_window(&[], 2, 5), Ok(Some(5)));
+        // too many buses to sweep through a whole period
+        let swept = [
+            999983, 999979, 999961, 999959, 999953, 999931, 999917, 999907,
+        ];
+        assert_eq!(earliest_window(&swept, 1000, 0), Err(Overflow));
+    }
+
+    #[test]
+    fn test_wide_windows() {
+        let buses = [29, 41, 661, 13, 17, 23, 521, 37, 19];
+        assert_eq!(earliest_window(&buses, 60, 1000677), Ok(Some(1001355)));
+        assert_eq!(earliest_window(&buses, 300, 1000677), Ok(Some(1000677)));
+        assert_eq!(earliest_window(&buses, 10, 1000677), Ok(Some(3638134)));
+        assert_eq!(earliest_window(&buses, 20, 1000677), Ok(Some(1015937)));
+        assert_eq!(earliest_window(&buses, 0, 0), Ok(Some(0)));
+        assert_eq!(earliest_window(&buses, 0, 1), Ok(Some(1463175673841141)));
+
+        let brute_force = |buses: &[usize], window: usize, time: usize| {
+            (time..)
+                .find(|t| buses.iter().all(|bus| (bus - t % bus) % bus <= window))
+                .unwrap()
+        };
+        for window in 0..8 {
+            for time in [0, 1, 50, 1000] {
+                let buses = [4, 6, 9, 10, 15, 35, 6];
+                assert_eq!(
+                    earliest_window(&buses, window, time),
+                    Ok(Some(brute_force(&buses, window, time)))
+                );
+            }
+        }
     }
 }
@@ -1,14 +1,38 @@
 use once_cell::sync::Lazy;
 use regex::Regex;
-use std::{collections::HashMap, str::FromStr};
+use std::{collections::HashMap, fmt, str::FromStr};
 
-#[derive(Clone, Copy, Debug, Default)]
+const MAX_WIDTH: usize = usize::BITS as usize;
+/// Word width of the ship's computer.
+const DEFAULT_WIDTH: usize = 36;
+
+/// All ones in the lowest `width` bits.
+fn width_mask(width: usize) -> usize {
+    if width == 0 {
+        0
+    } else {
+        usize::MAX >> (MAX_WIDTH - width)
+    }
+}
+
+/// Bits outside of `width` are left untouched by both decoders.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 struct CompositeMask {
     mask: usize,
     val: usize,
+    width: usize,
 }
 
 impl CompositeMask {
+    /// Mask in effect before the first `mask` line: all `width` bits float.
+    fn all_floating(width: usize) -> Self {
+        Self {
+            mask: 0,
+            val: 0,
+            width,
+        }
+    }
+
     fn apply(&self, v: usize) -> usize {
         v & !(self.mask & !self.val) | self.val
     }
@@ -16,6 +40,125 @@ impl CompositeMask {
     fn iter_apply_v2(&self, v: usize) -> CompositeMaskApplyV2Iter {
         CompositeMaskApplyV2Iter::new(self, v)
     }
+
+    fn floating(&self) -> usize {
+        !self.mask & width_mask(self.width)
+    }
+
+    fn pattern_v2(&self, v: usize) -> AddressPattern {
+        AddressPattern {
+            floating: self.floating(),
+            fixed: v & !self.floating() | self.val,
+        }
+    }
+}
+
+impl fmt::Display for CompositeMask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for idx in (0..self.width).rev() {
+            let c = match ((self.mask >> idx) & 1, (self.val >> idx) & 1) {
+                (0, _) => 'X',
+                (_, 0) => '0',
+                _ => '1',
+            };
+            write!(f, "{}", c)?;
+        }
+        Ok(())
+    }
+}
+
+/// Set of addresses where `floating` bits can be anything and the rest equal `fixed`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct AddressPattern {
+    floating: usize,
+    /// always 0 on floating bits
+    fixed: usize,
+}
+
+impl AddressPattern {
+    fn len(&self) -> u128 {
+        1 << self.floating.count_ones()
+    }
+
+    fn contains(&self, address: usize) -> bool {
+        address & !self.floating == self.fixed
+    }
+
+    fn render(self, width: usize) -> String {
+        (0..width)
+            .rev()
+            .map(
+                |idx| match ((self.floating >> idx) & 1, (self.fixed >> idx) & 1) {
+                    (1, _) => 'X',
+                    (_, 0) => '0',
+                    _ => '1',
+                },
+            )
+            .collect()
+    }
+
+    fn overlaps(&self, other: &Self) -> bool {
+        let both_fixed = !self.floating & !other.floating;
+        (self.fixed ^ other.fixed) & both_fixed == 0
+    }
+
+    /// Splits `self` minus `other` into non-overlapping patterns.
+    fn subtract(&self, other: &Self) -> Vec<AddressPattern> {
+        if !self.overlaps(other) {
+            return vec![*self];
+        }
+        let mut pieces = Vec::new();
+        let mut rest = *self;
+        let mut to_fix = self.floating & !other.floating;
+        while to_fix != 0 {
+            let bit = to_fix & to_fix.wrapping_neg();
+            to_fix &= !bit;
+            rest.floating &= !bit;
+            // the piece differing from `other` on this bit can't overlap it
+            pieces.push(AddressPattern {
+                floating: rest.floating,
+                fixed: rest.fixed | (!other.fixed & bit),
+            });
+            rest.fixed |= other.fixed & bit;
+        }
+        pieces
+    }
+}
+
+/// Decoder v2 memory kept as non-overlapping address patterns instead of single addresses.
+#[derive(Clone, Debug, Default)]
+struct FloatingMemory {
+    entries: Vec<(AddressPattern, usize)>,
+}
+
+impl FloatingMemory {
+    fn write(&mut self, pattern: AddressPattern, val: usize) {
+        let mut entries = Vec::with_capacity(self.entries.len() + 1);
+        for (existing, existing_val) in &self.entries {
+            entries.extend(
+                existing
+                    .subtract(&pattern)
+                    .into_iter()
+                    .map(|piece| (piece, *existing_val)),
+            );
+        }
+        entries.push((pattern, val));
+        self.entries = entries;
+    }
+
+    fn get(&self, address: usize) -> Option<usize> {
+        self.entries
+            .iter()
+            .find(|(pattern, _)| pattern.contains(address))
+            .map(|(_, val)| *val)
+    }
+
+    fn sum(&self) -> u128 {
+        self.entries
+            .iter()
+            .map(|(pattern, val)| pattern.len() * *val as u128)
+            .sum()
+    }
 }
 
 struct CompositeMaskApplyV2Iter {
@@ -28,17 +171,17 @@ impl CompositeMaskApplyV2Iter {
     fn new(mask: &CompositeMask, base: usize) -> Self {
         let bits = {
             let mut bit_indices = Vec::new();
-            let mut mask = mask.mask;
-            for idx in 0..36 {
-                if mask & 1 == 0 {
+            let mut floating = mask.floating();
+            for idx in 0..mask.width {
+                if floating & 1 == 1 {
                     bit_indices.push(idx);
                 }
-                mask >>= 1;
+                floating >>= 1;
             }
             bit_indices
         };
         Self {
-            val: base & mask.mask | mask.val,
+            val: base & !mask.floating() | mask.val,
             next_bits: 0,
             bits,
         }
@@ -68,7 +211,7 @@ impl FromStr for CompositeMask {
     type Err = ();
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if s.chars().any(|c| c != 'X' && c != '0' && c != '1') {
+        if s.len() > MAX_WIDTH || s.chars().any(|c| c != 'X' && c != '0' && c != '1') {
             return Err(());
         }
         let mask = s
@@ -79,7 +222,11 @@ impl FromStr for CompositeMask {
             .chars()
             .map(|c| if c == '1' { 1 } else { 0 })
             .fold(0, |acc, bit| (acc << 1) | bit);
-        Ok(Self { mask, val })
+        Ok(Self {
+            mask,
+            val,
+            width: s.len(),
+        })
     }
 }
 
@@ -89,8 +236,9 @@ enum Instr {
     Set { key: usize, val: usize },
 }
 
-static MASK_PATTERN: Lazy<Regex> = Lazy::new(|| Regex::new(r"mask = ([X01]{36})").unwrap());
-static MEM_PATTERN: Lazy<Regex> = Lazy::new(|| Regex::new(r"mem\[([0-9]+)\] = ([0-9]+)").unwrap());
+static MASK_PATTERN: Lazy<Regex> = Lazy::new(|| Regex::new(r"^mask = ([X01]+)$").unwrap());
+static MEM_PATTERN: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^mem\[([0-9]+)\] = ([0-9]+)$").unwrap());
 
 impl FromStr for Instr {
     type Err = ();
@@ -109,25 +257,33 @@ impl FromStr for Instr {
     }
 }
 
-fn execute_all(instrs: &[Instr]) -> HashMap<usize, usize> {
+fn execute_all(instrs: &[Instr], width: usize) -> HashMap<usize, usize> {
+    execute_all_traced(instrs, width)
+        .into_iter()
+        .map(|(key, (val, _))| (key, val))
+        .collect()
+}
+
+/// Like `execute_all` but also remembers the mask each value was written with.
+fn execute_all_traced(instrs: &[Instr], width: usize) -> HashMap<usize, (usize, CompositeMask)> {
     let mut mem = HashMap::new();
-    let mut mask = CompositeMask::default();
+    let mut mask = CompositeMask::all_floating(width);
     for instr in instrs {
         match instr {
             Instr::Mask(new_mask) => {
                 mask = *new_mask;
             }
             Instr::Set { key, val } => {
-                mem.insert(*key, mask.apply(*val));
+                mem.insert(*key, (mask.apply(*val), mask));
             }
         }
     }
     mem
 }
 
-fn execute_all_v2(instrs: &[Instr]) -> HashMap<usize, usize> {
+fn execute_all_v2(instrs: &[Instr], width: usize) -> HashMap<usize, usize> {
     let mut mem = HashMap::new();
-    let mut mask = CompositeMask::default();
+    let mut mask = CompositeMask::all_floating(width);
     for instr in instrs {
         match instr {
             Instr::Mask(new_mask) => {
@@ -143,21 +299,193 @@ fn execute_all_v2(instrs: &[Instr]) -> HashMap<usize, usize> {
     mem
 }
 
-fn parse_input(input: &str) -> Result<Vec<Instr>, ()> {
+fn execute_all_v2_floating(instrs: &[Instr], width: usize) -> FloatingMemory {
+    let mut mem = FloatingMemory::default();
+    let mut mask = CompositeMask::all_floating(width);
+    for instr in instrs {
+        match instr {
+            Instr::Mask(new_mask) => {
+                mask = *new_mask;
+            }
+            Instr::Set { key, val } => {
+                mem.write(mask.pattern_v2(*key), *val);
+            }
+        }
+    }
+    mem
+}
+
+/// Parses a program for a machine with `width` bit words.
+fn parse_input(input: &str, width: usize) -> Result<Vec<Instr>, ()> {
+    if width > MAX_WIDTH {
+        return Err(());
+    }
     input
         .lines()
-        .map(|line| line.parse::<Instr>())
+        .map(|line| {
+            let instr = line.parse::<Instr>()?;
+            let fits = match instr {
+                Instr::Mask(mask) => mask.width == width,
+                Instr::Set { key, val } => (key | val) & !width_mask(width) == 0,
+            };
+            if fits {
+                Ok(instr)
+            } else {
+                Err(())
+            }
+        })
         .collect::<Result<Vec<_>, _>>()
 }
 
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum DiffEntry {
+    Changed {
+        address: usize,
+        v1: usize,
+        v2: usize,
+    },
+    OnlyV1 {
+        address: usize,
+        val: usize,
+    },
+    /// `addresses` is the number of addresses in `pattern` that v1 never wrote
+    OnlyV2 {
+        pattern: AddressPattern,
+        val: usize,
+        addresses: u128,
+    },
+}
+
+fn diff(v1: &HashMap<usize, usize>, v2: &FloatingMemory) -> Vec<DiffEntry> {
+    let mut v1_addresses: Vec<_> = v1.keys().copied().collect();
+    v1_addresses.sort_unstable();
+    let mut entries = Vec::new();
+    for address in &v1_addresses {
+        let v1_val = v1[address];
+        match v2.get(*address) {
+            Some(v2_val) if v2_val == v1_val => {}
+            Some(v2_val) => entries.push(DiffEntry::Changed {
+                address: *address,
+                v1: v1_val,
+                v2: v2_val,
+            }),
+            None => entries.push(DiffEntry::OnlyV1 {
+                address: *address,
+                val: v1_val,
+            }),
+        }
+    }
+    let mut patterns = v2.entries.clone();
+    patterns.sort_unstable_by_key(|(pattern, _)| (pattern.fixed, pattern.floating));
+    for (pattern, val) in patterns {
+        let shared = v1_addresses
+            .iter()
+            .filter(|address| pattern.contains(**address))
+            .count() as u128;
+        if shared < pattern.len() {
+            entries.push(DiffEntry::OnlyV2 {
+                pattern,
+                val,
+                addresses: pattern.len() - shared,
+            });
+        }
+    }
+    entries
+}
+
+fn dump_v1(mem: &HashMap<usize, (usize, CompositeMask)>, width: usize) -> String {
+    let mut addresses: Vec<_> = mem.keys().copied().collect();
+    addresses.sort_unstable();
+    let mut out = String::new();
+    for address in addresses {
+        let (val, mask) = mem[&address];
+        out.push_str(&format!(
+            "mem[{}] = {}\n    value {:0width$b}\n    mask  {}\n",
+            address,
+            val,
+            val,
+            mask,
+            width = width
+        ));
+    }
+    out
+}
+
+fn dump_v2(mem: &FloatingMemory, width: usize) -> String {
+    let mut entries = mem.entries.clone();
+    entries.sort_unstable_by_key(|(pattern, _)| (pattern.fixed, pattern.floating));
+    let mut out = String::new();
+    for (pattern, val) in entries {
+        out.push_str(&format!(
+            "mem[{}] = {} ({} addresses)\n    value {:0width$b}\n    addr  {}\n",
+            pattern.fixed,
+            val,
+            pattern.len(),
+            val,
+            pattern.render(width),
+            width = width
+        ));
+    }
+    out
+}
+
 fn main() {
+    // usage: day14 [--width bits] [--expand | --dump-v1 | --dump-v2 | --diff]
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let has_flag = |flag: &str| args.iter().any(|arg| arg == flag);
+    let width = args
+        .iter()
+        .position(|arg| arg == "--width")
+        .map_or(DEFAULT_WIDTH, |pos| {
+            args.get(pos + 1)
+                .and_then(|arg| arg.parse().ok())
+                .expect("width should be an int")
+        });
     let instrs: Vec<_> =
-        parse_input(include_str!("../../inputs/day14.txt")).expect("input should parse");
-    println!("Part 1: {}", execute_all(&instrs).values().sum::<usize>());
-    println!(
-        "Part 2: {}",
-        execute_all_v2(&instrs).values().sum::<usize>()
-    );
+        parse_input(include_str!("../../inputs/day14.txt"), width).expect("input should parse");
+
+    if has_flag("--dump-v1") {
+        print!("{}", dump_v1(&execute_all_traced(&instrs, width), width));
+    } else if has_flag("--dump-v2") {
+        print!(
+            "{}",
+            dump_v2(&execute_all_v2_floating(&instrs, width), width)
+        );
+    } else if has_flag("--diff") {
+        let v1 = execute_all(&instrs, width);
+        for entry in diff(&v1, &execute_all_v2_floating(&instrs, width)) {
+            match entry {
+                DiffEntry::Changed { address, v1, v2 } => {
+                    println!("mem[{}]: v1 {} != v2 {}", address, v1, v2)
+                }
+                DiffEntry::OnlyV1 { address, val } => println!("mem[{}]: only v1 {}", address, val),
+                DiffEntry::OnlyV2 {
+                    pattern,
+                    val,
+                    addresses,
+                } => println!(
+                    "mem[{}]: only v2 {} at {} addresses",
+                    pattern.render(width),
+                    val,
+                    addresses
+                ),
+            }
+        }
+    } else {
+        println!(
+            "Part 1: {}",
+            execute_all(&instrs, width).values().sum::<usize>()
+        );
+        if has_flag("--expand") {
+            // expands every floating address, only feasible with few floating bits
+            println!(
+                "Part 2: {}",
+                execute_all_v2(&instrs, width).values().sum::<usize>()
+            );
+        } else {
+            println!("Part 2: {}", execute_all_v2_floating(&instrs, width).sum());
+        }
+    }
 }
 
 #[cfg(test)]
@@ -210,14 +538,154 @@ mod tests {
     fn test_part1_sample() {
         let instrs = parse_input(
             "mask = XXXXXXXXXXXXXXXXXXXXXXXXXXXXX1XXXX0X\nmem[8] = 11\nmem[7] = 101\nmem[8] = 0",
+            36,
         )
         .unwrap();
-        assert_eq!(execute_all(&instrs).values().sum::<usize>(), 165);
+        assert_eq!(execute_all(&instrs, 36).values().sum::<usize>(), 165);
     }
 
     #[test]
     fn test_part2_sample() {
-        let instrs = parse_input("mask = 000000000000000000000000000000X1001X\nmem[42] = 100\nmask = 00000000000000000000000000000000X0XX\nmem[26] = 1").unwrap();
-        assert_eq!(execute_all_v2(&instrs).values().sum::<usize>(), 208);
+        let instrs = parse_input("mask = 000000000000000000000000000000X1001X\nmem[42] = 100\nmask = 00000000000000000000000000000000X0XX\nmem[26] = 1", 36).unwrap();
+        assert_eq!(execute_all_v2(&instrs, 36).values().sum::<usize>(), 208);
+        assert_eq!(execute_all_v2_floating(&instrs, 36).sum(), 208);
+    }
+
+    #[test]
+    fn test_subtract() {
+        let everything = AddressPattern {
+            floating: 0b111,
+            fixed: 0,
+        };
+        let one = AddressPattern {
+            floating: 0,
+            fixed: 0b101,
+        };
+        let pieces = everything.subtract(&one);
+        assert_eq!(pieces.iter().map(AddressPattern::len).sum::<u128>(), 7);
+        for (idx, piece) in pieces.iter().enumerate() {
+            assert!(!piece.overlaps(&one));
+            for other in &pieces[idx + 1..] {
+                assert!(!piece.overlaps(other));
+            }
+        }
+        assert_eq!(one.subtract(&everything), vec![]);
+        let disjoint = AddressPattern {
+            floating: 0b011,
+            fixed: 0b000,
+        };
+        assert_eq!(disjoint.subtract(&one), vec![disjoint]);
+    }
+
+    #[test]
+    fn test_floating_matches_expansion() {
+        for program in [
+            "mask = 000000000000000000000000000000X1001X\nmem[42] = 100\nmask = 00000000000000000000000000000000X0XX\nmem[26] = 1",
+            "mask = 0000000000000000000000000000000000XX\nmem[0] = 7\nmem[1] = 3\nmask = 000000000000000000000000000000000X10\nmem[4] = 9",
+            "mask = 00000000000000000000000000000000XXXX\nmem[0] = 1\nmask = 000000000000000000000000000000001X0X\nmem[2] = 5\nmask = 00000000000000000000000000000000X1X1\nmem[0] = 2",
+            "mask = 0000000000000000000000000000XX0X1X0X\nmem[200] = 11\nmem[17] = 12\nmask = 00000000000000000000000000001XX0X0XX\nmem[3] = 13\nmask = 000000000000000000000000000000000000\nmem[45] = 14",
+        ] {
+            let instrs = parse_input(program, 36).unwrap();
+            let expanded = execute_all_v2(&instrs, 36);
+            let floating = execute_all_v2_floating(&instrs, 36);
+            assert_eq!(
+                floating.sum(),
+                expanded.values().sum::<usize>() as u128,
+                "{}",
+                program
+            );
+            assert_eq!(
+                floating.entries.iter().map(|(p, _)| p.len()).sum::<u128>(),
+                expanded.len() as u128
+            );
+        }
+    }
+
+    #[test]
+    fn test_many_floating_bits() {
+        let instrs = parse_input(
+            "mask = XXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX\nmem[0] = 1\nmask = 000000000000000000000000000000000000\nmem[0] = 3",
+            36,
+        )
+        .unwrap();
+        assert_eq!(
+            execute_all_v2_floating(&instrs, 36).sum(),
+            (1 << 36) - 1 + 3
+        );
+    }
+
+    #[test]
+    fn test_writes_before_mask() {
+        let instrs = parse_input("mem[3] = 5\nmask = 0000\nmem[1] = 2", 4).unwrap();
+        assert_eq!(execute_all(&instrs, 4), HashMap::from([(3, 5), (1, 0)]));
+        // all 4 bits float, so every address of the machine is written
+        let expanded = execute_all_v2(&instrs, 4);
+        assert_eq!(expanded.len(), 16);
+        assert!(expanded.keys().all(|address| *address < 16));
+        let v2 = execute_all_v2_floating(&instrs, 4);
+        assert_eq!(v2.sum(), 15 * 5 + 2);
+        assert_eq!(v2.get(15), Some(5));
+        assert_eq!(v2.get(16), None);
+        assert_eq!(CompositeMask::all_floating(4).to_string(), "XXXX");
+        assert_eq!(CompositeMask::all_floating(36).to_string(), "X".repeat(36));
+    }
+
+    #[test]
+    fn test_width() {
+        assert_eq!(
+            parse_input("mask = X1\nmem[3] = 3", 2).map(|instrs| execute_all(&instrs, 2)),
+            Ok(HashMap::from([(3, 3)]))
+        );
+        assert!(parse_input("mask = X1\nmem[4] = 3", 2).is_err());
+        assert!(parse_input("mask = X1\nmem[3] = 3", 3).is_err());
+
+        let mask = "X".repeat(64);
+        let instrs = parse_input(&format!("mask = {}\nmem[{}] = 2", mask, usize::MAX), 64).unwrap();
+        assert_eq!(execute_all(&instrs, 64), HashMap::from([(usize::MAX, 2)]));
+        assert_eq!(execute_all_v2_floating(&instrs, 64).sum(), 2 << 64);
+
+        let mask = format!("1{}", "0".repeat(63));
+        let instrs = parse_input(&format!("mask = {}\nmem[1] = 1", mask), 64).unwrap();
+        assert_eq!(execute_all(&instrs, 64), HashMap::from([(1, 1 << 63)]));
+        assert_eq!(
+            execute_all_v2(&instrs, 64),
+            HashMap::from([(1 | 1 << 63, 1)])
+        );
+    }
+
+    #[test]
+    fn test_dump_and_diff() {
+        let instrs = parse_input("mask = X10X\nmem[1] = 0\nmask = 000X\nmem[5] = 7", 4).unwrap();
+        assert_eq!(
+            dump_v1(&execute_all_traced(&instrs, 4), 4),
+            "mem[1] = 4\n    value 0100\n    mask  X10X\nmem[5] = 1\n    value 0001\n    mask  000X\n"
+        );
+        let v2 = execute_all_v2_floating(&instrs, 4);
+        assert_eq!(
+            dump_v2(&v2, 4),
+            "mem[4] = 7 (2 addresses)\n    value 0111\n    addr  010X\nmem[12] = 0 (2 addresses)\n    value 0000\n    addr  110X\n"
+        );
+        let at = |fixed, floating| AddressPattern { floating, fixed };
+        assert_eq!(
+            diff(&execute_all(&instrs, 4), &v2),
+            vec![
+                DiffEntry::OnlyV1 { address: 1, val: 4 },
+                DiffEntry::Changed {
+                    address: 5,
+                    v1: 1,
+                    v2: 7
+                },
+                DiffEntry::OnlyV2 {
+                    pattern: at(4, 1),
+                    val: 7,
+                    addresses: 1
+                },
+                DiffEntry::OnlyV2 {
+                    pattern: at(12, 1),
+                    val: 0,
+                    addresses: 2
+                },
+            ]
+        );
     }
 }
@@ -1,54 +1,379 @@
 use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Default cap on the memory of both turn tables, 256 MiB.
+const DEFAULT_MEMORY_BUDGET: usize = 256 << 20;
+
+const SNAPSHOT_MAGIC: &[u8; 8] = b"VANECK\x00\x02";
+
+/// The turn tables would outgrow the memory budget.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct OutOfMemory {
+    budget: usize,
+    /// turn whose number couldn't be remembered
+    turn: u32,
+}
+
+impl fmt::Display for OutOfMemory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "turn {} needs more than the memory budget of {} bytes",
+            self.turn, self.budget
+        )
+    }
+}
+
+/// Reasons a game can't be set up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum GameError {
+    NoStartingNumbers,
+    OutOfMemory(OutOfMemory),
+}
+
+impl fmt::Display for GameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GameError::NoStartingNumbers => write!(f, "there are no starting numbers"),
+            GameError::OutOfMemory(err) => err.fmt(f),
+        }
+    }
+}
+
+impl From<OutOfMemory> for GameError {
+    fn from(err: OutOfMemory) -> Self {
+        GameError::OutOfMemory(err)
+    }
+}
+
+/// Estimated size of a hash map with room for `capacity` entries: buckets are at most
+/// 7/8 full and each has a control byte next to its entry.
+fn sparse_bytes(capacity: usize) -> usize {
+    if capacity == 0 {
+        return 0;
+    }
+    let buckets = (capacity * 8).div_ceil(7).next_power_of_two();
+    buckets * (std::mem::size_of::<(u32, u32)>() + 1)
+}
 
 #[derive(Clone, Debug)]
 struct Game {
-    num_to_last_turn: HashMap<usize, usize>,
-    last_num: usize,
-    last_turn: usize,
+    starting_nums: Vec<u32>,
+    /// turn (1-based) in which a number was last spoken, 0 if never; indexed by number
+    dense_last_turn: Vec<u32>,
+    /// bytes both tables may take together
+    budget: usize,
+    /// maximum length of `dense_last_turn`, half of the budget
+    dense_limit: usize,
+    /// last turns of numbers that don't fit into the dense part
+    sparse_last_turn: HashMap<u32, u32>,
+    last_num: u32,
+    /// turn in which `last_num` was spoken
+    last_turn: u32,
 }
 
 impl Game {
-    fn new(starting_nums: &[usize]) -> Self {
-        let mut num_to_last_turn = HashMap::with_capacity(starting_nums.len());
-        let (last_num, prev_nums) = starting_nums.split_last().unwrap();
-        for (turn, num) in prev_nums.iter().enumerate() {
-            num_to_last_turn.insert(*num, turn);
-        }
-        Self {
-            num_to_last_turn,
+    fn new(starting_nums: &[u32]) -> Self {
+        Self::with_memory_budget(starting_nums, DEFAULT_MEMORY_BUDGET)
+            .expect("starting numbers should be given and fit into the default budget")
+    }
+
+    /// Keeps both turn tables within roughly `bytes`: numbers below a limit are looked up
+    /// in a dense table taking up to half of it, larger ones in a hash map taking the
+    /// rest. Every number spoken needs an entry; 10^9 turns fit into 1.5 GiB and the
+    /// default budget lasts about 2 * 10^8 turns.
+    fn with_memory_budget(starting_nums: &[u32], bytes: usize) -> Result<Self, GameError> {
+        let (last_num, prev_nums) = starting_nums
+            .split_last()
+            .ok_or(GameError::NoStartingNumbers)?;
+        let mut game = Self {
+            starting_nums: starting_nums.to_vec(),
+            dense_last_turn: Vec::new(),
+            budget: bytes,
+            dense_limit: bytes / 2 / std::mem::size_of::<u32>(),
+            sparse_last_turn: HashMap::new(),
             last_num: *last_num,
-            last_turn: prev_nums.len(),
+            last_turn: starting_nums.len() as u32,
+        };
+        for (idx, num) in prev_nums.iter().enumerate() {
+            game.remember(*num, idx as u32 + 1)?;
         }
+        Ok(game)
     }
-}
 
-impl Iterator for Game {
-    type Item = usize;
+    fn fits(&self, dense_len: usize, sparse_capacity: usize) -> bool {
+        dense_len * std::mem::size_of::<u32>() + sparse_bytes(sparse_capacity) <= self.budget
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let num = if let Some(turn) = self.num_to_last_turn.get(&self.last_num) {
-            self.last_turn - turn
-        } else {
+    /// Stores `turn` as the last time `num` was spoken, returns the previous one (0 if none).
+    /// Leaves the tables as they are if they would have to grow beyond the budget.
+    fn remember(&mut self, num: u32, turn: u32) -> Result<u32, OutOfMemory> {
+        let out_of_memory = OutOfMemory {
+            budget: self.budget,
+            turn,
+        };
+        let idx = num as usize;
+        if idx < self.dense_limit {
+            if idx >= self.dense_last_turn.len() {
+                let new_len = (idx + 1).next_power_of_two().min(self.dense_limit);
+                if !self.fits(new_len, self.sparse_last_turn.capacity()) {
+                    return Err(out_of_memory);
+                }
+                self.dense_last_turn
+                    .reserve_exact(new_len - self.dense_last_turn.len());
+                self.dense_last_turn.resize(new_len, 0);
+            }
+            return Ok(std::mem::replace(&mut self.dense_last_turn[idx], turn));
+        }
+        if let Some(last_turn) = self.sparse_last_turn.get_mut(&num) {
+            return Ok(std::mem::replace(last_turn, turn));
+        }
+        let len = self.sparse_last_turn.len();
+        if len == self.sparse_last_turn.capacity() {
+            let capacity = (2 * len).max(8);
+            if !self.fits(self.dense_last_turn.capacity(), capacity) {
+                return Err(out_of_memory);
+            }
+            self.sparse_last_turn.reserve(capacity - len);
+        }
+        self.sparse_last_turn.insert(num, turn);
+        Ok(0)
+    }
+
+    /// Number spoken in the given (1-based) turn. Advances the game, so earlier turns can't
+    /// be asked for afterwards (apart from the starting numbers).
+    fn nth_spoken(&mut self, turn: u32) -> Result<u32, OutOfMemory> {
+        assert!(turn > 0, "turns start at 1");
+        if let Some(num) = self.starting_nums.get(turn as usize - 1) {
+            return Ok(*num);
+        }
+        assert!(
+            turn >= self.last_turn,
+            "turn {} is already gone, the game is at turn {}",
+            turn,
+            self.last_turn
+        );
+        while self.last_turn < turn {
+            self.step()?;
+        }
+        Ok(self.last_num)
+    }
+
+    fn step(&mut self) -> Result<u32, OutOfMemory> {
+        let prev_turn = self.remember(self.last_num, self.last_turn)?;
+        let num = if prev_turn == 0 {
             0
+        } else {
+            self.last_turn - prev_turn
         };
-        self.num_to_last_turn.insert(self.last_num, self.last_turn);
         self.last_num = num;
-        self.last_turn += 1;
-        Some(num)
+        self.last_turn = self
+            .last_turn
+            .checked_add(1)
+            .expect("turn should fit into u32");
+        Ok(num)
     }
 }
 
+/// Snapshot layout, all integers little-endian u32 unless noted:
+/// magic, memory budget (u64), last num, last turn, starting nums (count + values),
+/// dense table without trailing zeros (count + values), sparse table (count + pairs).
+impl Game {
+    fn write_snapshot<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let used = self
+            .dense_last_turn
+            .iter()
+            .rposition(|turn| *turn != 0)
+            .map_or(0, |idx| idx + 1);
+        writer.write_all(SNAPSHOT_MAGIC)?;
+        writer.write_all(&(self.budget as u64).to_le_bytes())?;
+        write_u32(&mut writer, self.last_num)?;
+        write_u32(&mut writer, self.last_turn)?;
+        write_u32s(&mut writer, &self.starting_nums)?;
+        write_u32s(&mut writer, &self.dense_last_turn[..used])?;
+        let mut sparse: Vec<_> = self.sparse_last_turn.iter().collect();
+        sparse.sort_unstable();
+        write_u32(&mut writer, sparse.len() as u32)?;
+        for (num, turn) in sparse {
+            write_u32(&mut writer, *num)?;
+            write_u32(&mut writer, *turn)?;
+        }
+        writer.flush()
+    }
+
+    fn read_snapshot<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != SNAPSHOT_MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a day15 snapshot",
+            ));
+        }
+        let mut budget = [0; 8];
+        reader.read_exact(&mut budget)?;
+        let budget = u64::from_le_bytes(budget) as usize;
+        let dense_limit = budget / 2 / std::mem::size_of::<u32>();
+        let last_num = read_u32(&mut reader)?;
+        let last_turn = read_u32(&mut reader)?;
+        let starting_nums = read_u32s(&mut reader)?;
+        let dense_last_turn = read_u32s(&mut reader)?;
+        // the entries themselves bound the table, not the count the snapshot claims
+        let sparse_len = read_u32(&mut reader)?;
+        let mut sparse_last_turn = HashMap::new();
+        for _ in 0..sparse_len {
+            let num = read_u32(&mut reader)?;
+            sparse_last_turn.insert(num, read_u32(&mut reader)?);
+        }
+        let game = Self {
+            starting_nums,
+            dense_last_turn,
+            budget,
+            dense_limit,
+            sparse_last_turn,
+            last_num,
+            last_turn,
+        };
+        // remembered turns come before the current one, large numbers only live in the
+        // sparse table, keys are unique and both tables fit into the budget
+        let consistent = !game.starting_nums.is_empty()
+            && game.last_turn as usize >= game.starting_nums.len()
+            && game.dense_last_turn.len() <= dense_limit
+            && game.sparse_last_turn.len() == sparse_len as usize
+            && game
+                .sparse_last_turn
+                .keys()
+                .all(|num| *num as usize >= dense_limit)
+            && game
+                .dense_last_turn
+                .iter()
+                .chain(game.sparse_last_turn.values())
+                .all(|turn| *turn < last_turn)
+            && game.fits(game.dense_last_turn.len(), game.sparse_last_turn.len());
+        if !consistent {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "inconsistent day15 snapshot",
+            ));
+        }
+        Ok(game)
+    }
+
+    /// Writes the snapshot next to `path` first so a crash never leaves a broken file behind.
+    fn save(&self, path: &Path) -> io::Result<()> {
+        let tmp_path = PathBuf::from(format!("{}.tmp", path.display()));
+        self.write_snapshot(BufWriter::new(File::create(&tmp_path)?))?;
+        fs::rename(tmp_path, path)
+    }
+
+    fn load(path: &Path) -> io::Result<Self> {
+        Self::read_snapshot(BufReader::new(File::open(path)?))
+    }
+
+    /// Like `nth_spoken` but saves a snapshot to `path` every `every` turns.
+    fn nth_spoken_with_checkpoints(
+        &mut self,
+        turn: u32,
+        every: u32,
+        path: &Path,
+    ) -> io::Result<u32> {
+        assert!(every > 0);
+        while self.last_turn < turn {
+            let next_checkpoint = (self.last_turn / every + 1).saturating_mul(every);
+            if next_checkpoint >= turn {
+                break;
+            }
+            self.nth_spoken(next_checkpoint).map_err(out_of_memory)?;
+            self.save(path)?;
+        }
+        self.nth_spoken(turn).map_err(out_of_memory)
+    }
+}
+
+fn out_of_memory(err: OutOfMemory) -> io::Error {
+    io::Error::new(io::ErrorKind::OutOfMemory, err.to_string())
+}
+
+fn write_u32<W: Write>(writer: &mut W, num: u32) -> io::Result<()> {
+    writer.write_all(&num.to_le_bytes())
+}
+
+fn write_u32s<W: Write>(writer: &mut W, nums: &[u32]) -> io::Result<()> {
+    write_u32(writer, nums.len() as u32)?;
+    for num in nums {
+        write_u32(writer, *num)?;
+    }
+    Ok(())
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u32s<R: Read>(reader: &mut R) -> io::Result<Vec<u32>> {
+    let len = read_u32(reader)?;
+    (0..len).map(|_| read_u32(reader)).collect()
+}
+
+/// Numbers spoken after the starting ones, an `Err` once the turn tables are full.
+impl Iterator for Game {
+    type Item = Result<u32, OutOfMemory>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.step())
+    }
+}
+
+fn or_exit<T, E: fmt::Display>(result: Result<T, E>) -> T {
+    result.unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    })
+}
+
 fn main() {
+    // usage: day15 [turn] [--budget MiB] [--checkpoint file [--every turns]] [--resume file]
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let arg_value = |flag: &str| {
+        args.iter().position(|arg| arg == flag).map(|pos| {
+            args.get(pos + 1)
+                .expect("flag should have a value")
+                .as_str()
+        })
+    };
+    let budget = arg_value("--budget").map_or(DEFAULT_MEMORY_BUDGET, |arg| {
+        arg.parse::<usize>().expect("budget should be an int") << 20
+    });
+    let starting_nums = [6, 4, 12, 1, 20, 0, 16];
+    if let Some(turn) = args.first().filter(|arg| !arg.starts_with("--")) {
+        let turn = turn.parse().expect("turn should be an int");
+        let mut game = match arg_value("--resume") {
+            Some(path) => or_exit(Game::load(Path::new(path))),
+            None => or_exit(Game::with_memory_budget(&starting_nums, budget)),
+        };
+        let num = match arg_value("--checkpoint") {
+            Some(path) => {
+                let every = arg_value("--every").map_or(10_000_000, |arg| {
+                    arg.parse().expect("every should be an int")
+                });
+                or_exit(game.nth_spoken_with_checkpoints(turn, every, Path::new(path)))
+            }
+            None => or_exit(game.nth_spoken(turn)),
+        };
+        println!("Turn {}: {}", turn, num);
+        return;
+    }
     println!(
         "Part 1: {}",
-        Game::new(&[6, 4, 12, 1, 20, 0, 16]).nth(2020 - 8).unwrap()
-    );
-    println!(
-        "Part 1: {}",
-        Game::new(&[6, 4, 12, 1, 20, 0, 16])
-            .nth(30000000 - 8)
-            .unwrap()
+        or_exit(Game::new(&starting_nums).nth_spoken(2020))
     );
+    let mut game = or_exit(Game::with_memory_budget(&starting_nums, budget));
+    println!("Part 2: {}", or_exit(game.nth_spoken(30000000)));
 }
 
 #[cfg(test)]
@@ -57,26 +382,165 @@ mod tests {
 
     #[test]
     fn test_iteration() {
+        let game = Game::new(&[0, 3, 6]);
+        assert_eq!(
+            game.take(7).collect::<Result<Vec<_>, _>>(),
+            Ok(vec![0, 3, 3, 1, 0, 4, 0])
+        );
+    }
+
+    #[test]
+    fn test_nth_spoken() {
         let mut game = Game::new(&[0, 3, 6]);
-        assert_eq!(game.next(), Some(0));
-        assert_eq!(game.next(), Some(3));
-        assert_eq!(game.next(), Some(3));
-        assert_eq!(game.next(), Some(1));
-        assert_eq!(game.next(), Some(0));
-        assert_eq!(game.next(), Some(4));
-        assert_eq!(game.next(), Some(0));
+        assert_eq!(game.nth_spoken(1).unwrap(), 0);
+        assert_eq!(game.nth_spoken(3).unwrap(), 6);
+        assert_eq!(game.nth_spoken(4).unwrap(), 0);
+        assert_eq!(game.nth_spoken(4).unwrap(), 0);
+        assert_eq!(game.nth_spoken(9).unwrap(), 4);
+        assert_eq!(game.nth_spoken(2).unwrap(), 3);
+        assert_eq!(game.nth_spoken(10).unwrap(), 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_nth_spoken_past_turn() {
+        let mut game = Game::new(&[0, 3, 6]);
+        game.nth_spoken(10).unwrap();
+        game.nth_spoken(9).unwrap();
     }
 
     #[test]
     fn test_part1_samples() {
-        assert_eq!(Game::new(&[1, 3, 2]).nth(2020 - 4), Some(1));
-        assert_eq!(Game::new(&[2, 1, 3]).nth(2020 - 4), Some(10));
+        assert_eq!(Game::new(&[1, 3, 2]).nth_spoken(2020).unwrap(), 1);
+        assert_eq!(Game::new(&[2, 1, 3]).nth_spoken(2020).unwrap(), 10);
+        assert_eq!(Game::new(&[3, 1, 2]).nth_spoken(2020).unwrap(), 1836);
+    }
+
+    #[test]
+    fn test_sparse_overflow() {
+        let mut dense = Game::new(&[3, 1, 2]);
+        let mut sparse = Game::with_memory_budget(&[3, 1, 2], 256 << 10).unwrap();
+        for turn in (4..100000).step_by(997) {
+            assert_eq!(
+                dense.nth_spoken(turn).unwrap(),
+                sparse.nth_spoken(turn).unwrap()
+            );
+        }
+        assert!(sparse.dense_last_turn.len() <= 32 << 10);
+        assert!(!sparse.sparse_last_turn.is_empty());
+    }
+
+    #[test]
+    fn test_memory_budget() {
+        let budget = 64 << 10;
+        let mut game = Game::with_memory_budget(&[0, 3, 6], budget).unwrap();
+        let mut unlimited = Game::new(&[0, 3, 6]);
+        let err = loop {
+            match game.step() {
+                Ok(num) => assert_eq!(Ok(num), unlimited.step()),
+                Err(err) => break err,
+            }
+            assert!(game.fits(
+                game.dense_last_turn.capacity(),
+                game.sparse_last_turn.capacity()
+            ));
+        };
+        assert_eq!(err.budget, budget);
+        assert_eq!(err.turn, game.last_turn);
+        assert!(!game.sparse_last_turn.is_empty());
+        // running out leaves the game as it was
+        assert_eq!(game.step(), Err(err));
+        assert_eq!(game.next(), Some(Err(err)));
+        assert!(matches!(
+            Game::with_memory_budget(&[1 << 20, 0], 64),
+            Err(GameError::OutOfMemory(_))
+        ));
+        let err = Game::with_memory_budget(&[], budget).unwrap_err();
+        assert_eq!(err, GameError::NoStartingNumbers);
+        assert_eq!(err.to_string(), "there are no starting numbers");
+    }
+
+    #[test]
+    fn test_snapshot_roundtrip() {
+        for budget in [DEFAULT_MEMORY_BUDGET, 128 << 10] {
+            let mut uninterrupted = Game::with_memory_budget(&[0, 3, 6], budget).unwrap();
+            let expected = uninterrupted.nth_spoken(50000).unwrap();
+
+            let mut game = Game::with_memory_budget(&[0, 3, 6], budget).unwrap();
+            game.nth_spoken(12345).unwrap();
+            let mut snapshot = Vec::new();
+            game.write_snapshot(&mut snapshot).unwrap();
+            let mut resumed = Game::read_snapshot(&snapshot[..]).unwrap();
+            assert_eq!(resumed.nth_spoken(2).unwrap(), 3);
+            assert_eq!(resumed.nth_spoken(50000).unwrap(), expected);
+            assert_eq!(resumed.dense_last_turn, uninterrupted.dense_last_turn);
+            assert_eq!(resumed.sparse_last_turn, uninterrupted.sparse_last_turn);
+        }
+        assert!(Game::read_snapshot(&b"garbage!"[..]).is_err());
+
+        let game = Game::with_memory_budget(&[0, 3, 6], 128 << 10).unwrap();
+        let snapshot = |game: &Game| {
+            let mut snapshot = Vec::new();
+            game.write_snapshot(&mut snapshot).unwrap();
+            snapshot
+        };
+        let mut corrupt = game.clone();
+        corrupt.last_turn = 2;
+        assert!(Game::read_snapshot(&snapshot(&corrupt)[..]).is_err());
+        let mut corrupt = game.clone();
+        corrupt.sparse_last_turn.insert(5, 1);
+        assert!(Game::read_snapshot(&snapshot(&corrupt)[..]).is_err());
+        let mut corrupt = game.clone();
+        corrupt.dense_last_turn[1] = 3;
+        assert!(Game::read_snapshot(&snapshot(&corrupt)[..]).is_err());
+        // the tables outgrew the budget
+        let mut corrupt = game.clone();
+        corrupt.sparse_last_turn = (0..20000).map(|num| ((1 << 20) + num, 1)).collect();
+        assert!(Game::read_snapshot(&snapshot(&corrupt)[..]).is_err());
+        // the claimed number of sparse entries isn't allocated up front
+        let mut truncated = snapshot(&game);
+        let sparse_len = truncated.len() - 4;
+        truncated[sparse_len..].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(Game::read_snapshot(&truncated[..]).is_err());
+        assert!(Game::read_snapshot(&snapshot(&game)[..]).is_ok());
+        assert!(Game::read_snapshot(&SNAPSHOT_MAGIC[..]).is_err());
+    }
+
+    #[test]
+    fn test_checkpoints() {
+        // snapshots may end in `.tmp` themselves
+        let path = std::env::temp_dir().join(format!("day15-test-{}.tmp", std::process::id()));
+        let expected = Game::new(&[3, 1, 2]).nth_spoken(30000).unwrap();
+        let mut game = Game::new(&[3, 1, 2]);
+        assert_eq!(
+            game.nth_spoken_with_checkpoints(25000, 1000, &path)
+                .unwrap(),
+            Game::new(&[3, 1, 2]).nth_spoken(25000).unwrap()
+        );
+        // the last checkpoint is from turn 24000, resuming replays the rest
+        let mut resumed = Game::load(&path).unwrap();
+        assert_eq!(resumed.last_turn, 24000);
+        assert_eq!(
+            resumed
+                .nth_spoken_with_checkpoints(30000, 1000, &path)
+                .unwrap(),
+            expected
+        );
+        assert_eq!(Game::load(&path).unwrap().last_turn, 29000);
+        fs::remove_file(&path).unwrap();
     }
 
     #[test]
     #[ignore]
     fn test_part2_samples() {
-        assert_eq!(Game::new(&[0, 3, 6]).nth(30000000 - 4), Some(175594));
-        assert_eq!(Game::new(&[2, 1, 3]).nth(30000000 - 4), Some(3544142));
+        assert_eq!(Game::new(&[0, 3, 6]).nth_spoken(30000000).unwrap(), 175594);
+        assert_eq!(Game::new(&[2, 1, 3]).nth_spoken(30000000).unwrap(), 3544142);
+    }
+
+    #[test]
+    #[ignore]
+    fn test_billion_turns() {
+        let mut game = Game::with_memory_budget(&[6, 4, 12, 1, 20, 0, 16], 1536 << 20).unwrap();
+        assert_eq!(game.nth_spoken(1_000_000_000).unwrap(), 1774539);
     }
 }
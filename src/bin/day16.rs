@@ -1,4 +1,5 @@
-use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::fmt;
 use std::mem;
 use std::str::FromStr;
 
@@ -33,9 +34,30 @@ struct Rule {
     ranges: Vec<RangeInclusive<usize>>,
 }
 
+/// Parses `a-b` or a single value `a`.
 fn parse_range(s: &str) -> Result<RangeInclusive<usize>, ()> {
-    let mut parts = s.split('-').map(|s| s.parse::<usize>().map_err(|_| ()));
-    Ok((parts.next().ok_or(())??)..=(parts.next().ok_or(())??))
+    let parse = |s: &str| s.trim().parse::<usize>().map_err(|_| ());
+    match s.split_once('-') {
+        Some((start, end)) => {
+            let (start, end) = (parse(start)?, parse(end)?);
+            if start > end {
+                return Err(());
+            }
+            Ok(start..=end)
+        }
+        None => {
+            let val = parse(s)?;
+            Ok(val..=val)
+        }
+    }
+}
+
+fn format_range(range: &RangeInclusive<usize>) -> String {
+    if range.start() == range.end() {
+        range.start().to_string()
+    } else {
+        format!("{}-{}", range.start(), range.end())
+    }
 }
 
 impl Rule {
@@ -46,6 +68,21 @@ impl Rule {
     fn in_range(&self, v: &usize) -> bool {
         self.ranges.iter().any(|range| range.contains(v))
     }
+
+    /// Range closest to `v` with the distance to it (0 if `v` is inside).
+    fn closest_range(&self, v: usize) -> Option<(&RangeInclusive<usize>, usize)> {
+        self.ranges
+            .iter()
+            .map(|range| {
+                let distance = if v < *range.start() {
+                    range.start() - v
+                } else {
+                    v.saturating_sub(*range.end())
+                };
+                (range, distance)
+            })
+            .min_by_key(|(_, distance)| *distance)
+    }
 }
 
 impl FromStr for Rule {
@@ -55,6 +92,9 @@ impl FromStr for Rule {
         let mut parts = s.split(':');
         let name = parts.next().ok_or(())?.to_owned();
         let raw_ranges = parts.next().ok_or(())?.trim();
+        if raw_ranges.is_empty() || parts.next().is_some() {
+            return Err(());
+        }
         let ranges = raw_ranges
             .split(" or ")
             .map(parse_range)
@@ -82,61 +122,313 @@ impl Input {
             .filter(|v| !self.is_valid_field_value(*v))
     }
 
-    fn discard_invalid_tickets(&mut self) {
+    /// Reports for nearby tickets that have values no rule accepts.
+    fn validate_tickets(&self) -> Vec<TicketReport> {
+        self.nearby_tickets
+            .iter()
+            .enumerate()
+            .filter_map(|(ticket, values)| {
+                let invalid: Vec<_> = values
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, val)| !self.is_valid_field_value(**val))
+                    .map(|(position, val)| InvalidValue {
+                        position,
+                        value: *val,
+                        closest: self.closest_miss(*val),
+                    })
+                    .collect();
+                if invalid.is_empty() {
+                    None
+                } else {
+                    Some(TicketReport { ticket, invalid })
+                }
+            })
+            .collect()
+    }
+
+    fn closest_miss(&self, val: usize) -> Option<ClosestMiss> {
+        self.rules
+            .iter()
+            .filter_map(|rule| {
+                let (range, distance) = rule.closest_range(val)?;
+                Some(ClosestMiss {
+                    rule: rule.name.clone(),
+                    range: range.clone(),
+                    distance,
+                })
+            })
+            .min_by_key(|miss| miss.distance)
+    }
+
+    /// Drops tickets with invalid values, returning why each of them was dropped.
+    fn discard_invalid_tickets(&mut self) -> Vec<TicketReport> {
+        let reports = self.validate_tickets();
         // stealing the vec for a second to modify it while still using an immutable ref to self for filtering
         let mut temp = mem::take(&mut self.nearby_tickets);
         temp.retain(|t| t.iter().all(|v| self.is_valid_field_value(*v)));
         self.nearby_tickets = temp;
+        reports
     }
 
-    fn figure_out_rules(&self) -> Vec<&Rule> {
-        let mut possible_rules: Vec<HashSet<&Rule>> =
-            vec![Default::default(); self.your_ticket.len()];
-        for rule in &self.rules {
-            for (idx, possible_rules_set) in possible_rules.iter_mut().enumerate() {
-                if self
-                    .nearby_tickets
+    /// Assigns a rule to every field position via maximum bipartite matching.
+    fn figure_out_rules(&self) -> Result<Vec<&Rule>, AssignmentError> {
+        // candidate rules for every position
+        let candidates: Vec<Vec<usize>> = (0..self.your_ticket.len())
+            .map(|idx| {
+                self.rules
                     .iter()
-                    .all(|ticket| rule.in_range(&ticket[idx]))
-                {
-                    possible_rules_set.insert(rule);
+                    .enumerate()
+                    .filter(|(_, rule)| {
+                        self.nearby_tickets
+                            .iter()
+                            .all(|ticket| rule.in_range(&ticket[idx]))
+                    })
+                    .map(|(rule_idx, _)| rule_idx)
+                    .collect()
+            })
+            .collect();
+
+        let matching = Matching::hopcroft_karp(&candidates, self.rules.len());
+        if matching.left.iter().any(Option::is_none) {
+            let (positions, rules) = matching.hall_violation(&candidates);
+            return Err(AssignmentError::Impossible {
+                positions,
+                fields: rules
+                    .into_iter()
+                    .map(|rule| self.rules[rule].name.clone())
+                    .collect(),
+            });
+        }
+
+        let ambiguous: Vec<String> = (0..candidates.len())
+            .filter(|pos| matching.has_alternative(&candidates, *pos))
+            .map(|pos| self.rules[matching.left[pos].unwrap()].name.clone())
+            .collect();
+        if !ambiguous.is_empty() {
+            return Err(AssignmentError::Ambiguous { fields: ambiguous });
+        }
+
+        Ok(matching
+            .left
+            .into_iter()
+            .map(|rule_idx| &self.rules[rule_idx.unwrap()])
+            .collect())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct ClosestMiss {
+    rule: String,
+    range: RangeInclusive<usize>,
+    distance: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct InvalidValue {
+    position: usize,
+    value: usize,
+    /// `None` only when there are no rules at all
+    closest: Option<ClosestMiss>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct TicketReport {
+    /// index among the nearby tickets
+    ticket: usize,
+    invalid: Vec<InvalidValue>,
+}
+
+impl fmt::Display for TicketReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "nearby ticket {} rejected:", self.ticket)?;
+        for invalid in &self.invalid {
+            write!(
+                f,
+                "\n    value {} at position {}",
+                invalid.value, invalid.position
+            )?;
+            if let Some(miss) = &invalid.closest {
+                write!(
+                    f,
+                    ", closest is {} {} (off by {})",
+                    miss.rule,
+                    format_range(&miss.range),
+                    miss.distance
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum AssignmentError {
+    /// positions that together have fewer candidate fields than there are positions
+    Impossible {
+        positions: Vec<usize>,
+        fields: Vec<String>,
+    },
+    /// fields that could be at more than one position
+    Ambiguous { fields: Vec<String> },
+}
+
+impl fmt::Display for AssignmentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssignmentError::Impossible { positions, fields } => {
+                let positions: Vec<_> = positions.iter().map(|pos| pos.to_string()).collect();
+                write!(
+                    f,
+                    "no valid assignment, the {} positions {} only fit the {} fields {}",
+                    positions.len(),
+                    positions.join(", "),
+                    fields.len(),
+                    fields.join(", ")
+                )
+            }
+            AssignmentError::Ambiguous { fields } => {
+                write!(
+                    f,
+                    "multiple valid assignments, ambiguous fields: {}",
+                    fields.join(", ")
+                )
+            }
+        }
+    }
+}
+
+/// Matching between left vertices (field positions) and right vertices (rules).
+struct Matching {
+    left: Vec<Option<usize>>,
+    right: Vec<Option<usize>>,
+}
+
+impl Matching {
+    fn hopcroft_karp(adj: &[Vec<usize>], right_count: usize) -> Self {
+        let mut matching = Self {
+            left: vec![None; adj.len()],
+            right: vec![None; right_count],
+        };
+        let mut dist = vec![usize::MAX; adj.len()];
+        while matching.bfs_layers(adj, &mut dist) {
+            for left in 0..adj.len() {
+                if matching.left[left].is_none() {
+                    matching.dfs_augment(adj, &mut dist, left);
                 }
             }
         }
+        matching
+    }
 
-        let mut figured_out: Vec<Option<&Rule>> = vec![None; self.your_ticket.len()];
-        while figured_out.iter().any(|rule| rule.is_none()) {
-            // find a not figured out rule that has only one possible rule
-            if let Some((found_idx, (figured_out_target, possible_rules_set))) = figured_out
-                .iter_mut()
-                .zip(possible_rules.iter())
-                .enumerate()
-                .find(|(_, (figured_out, possible_rules_set))| {
-                    figured_out.is_none() && possible_rules_set.len() == 1
-                })
-            {
-                // write it down in figure_out_target
-                let rule = *possible_rules_set
-                    .iter()
-                    .next()
-                    .expect("there should be one - we just found it");
-                *figured_out_target = Some(rule);
-
-                // remove the rule from other possible rules sets
-                for (idx, possible_rules_set) in possible_rules.iter_mut().enumerate() {
-                    if idx != found_idx {
-                        possible_rules_set.remove(rule);
+    /// Layers free left vertices at distance 0, returns whether an augmenting path exists.
+    fn bfs_layers(&self, adj: &[Vec<usize>], dist: &mut [usize]) -> bool {
+        let mut queue = VecDeque::new();
+        for (left, matched) in self.left.iter().enumerate() {
+            if matched.is_none() {
+                dist[left] = 0;
+                queue.push_back(left);
+            } else {
+                dist[left] = usize::MAX;
+            }
+        }
+        let mut found = false;
+        while let Some(left) = queue.pop_front() {
+            for right in &adj[left] {
+                match self.right[*right] {
+                    None => found = true,
+                    Some(next) if dist[next] == usize::MAX => {
+                        dist[next] = dist[left] + 1;
+                        queue.push_back(next);
                     }
+                    Some(_) => {}
                 }
-            } else {
-                panic!("can't figure it out");
             }
         }
+        found
+    }
 
-        possible_rules
-            .into_iter()
-            .map(|set| set.into_iter().next().unwrap())
-            .collect()
+    fn dfs_augment(&mut self, adj: &[Vec<usize>], dist: &mut [usize], left: usize) -> bool {
+        for right in &adj[left] {
+            let can_augment = match self.right[*right] {
+                None => true,
+                Some(next) => dist[next] == dist[left] + 1 && self.dfs_augment(adj, dist, next),
+            };
+            if can_augment {
+                self.left[left] = Some(*right);
+                self.right[*right] = Some(left);
+                return true;
+            }
+        }
+        dist[left] = usize::MAX;
+        false
+    }
+
+    /// For a maximum matching that leaves some left vertices free: the left vertices
+    /// reachable from them by alternating paths, and their neighbours. The neighbours are
+    /// all matched within the set, so there are fewer of them than left vertices.
+    fn hall_violation(&self, adj: &[Vec<usize>]) -> (Vec<usize>, Vec<usize>) {
+        let mut left_seen = vec![false; adj.len()];
+        let mut right_seen = vec![false; self.right.len()];
+        let mut queue: VecDeque<usize> = (0..adj.len())
+            .filter(|left| self.left[*left].is_none())
+            .collect();
+        for left in &queue {
+            left_seen[*left] = true;
+        }
+        while let Some(left) = queue.pop_front() {
+            for right in &adj[left] {
+                if right_seen[*right] {
+                    continue;
+                }
+                right_seen[*right] = true;
+                let next = self.right[*right].expect("a maximum matching has no augmenting path");
+                if !left_seen[next] {
+                    left_seen[next] = true;
+                    queue.push_back(next);
+                }
+            }
+        }
+        let seen = |flags: Vec<bool>| -> Vec<usize> {
+            flags
+                .into_iter()
+                .enumerate()
+                .filter(|(_, seen)| *seen)
+                .map(|(idx, _)| idx)
+                .collect()
+        };
+        (seen(left_seen), seen(right_seen))
+    }
+
+    /// Whether `left` could be matched differently while keeping everything else matched.
+    fn has_alternative(&self, adj: &[Vec<usize>], left: usize) -> bool {
+        let current = self.left[left].expect("left vertex should be matched");
+        let mut visited = vec![false; adj.len()];
+        visited[left] = true;
+        // look for an alternating path from `left` to a free rule or back to `current`
+        adj[left]
+            .iter()
+            .filter(|right| **right != current)
+            .any(|right| self.alternating_path(adj, *right, current, &mut visited))
+    }
+
+    fn alternating_path(
+        &self,
+        adj: &[Vec<usize>],
+        right: usize,
+        target: usize,
+        visited: &mut [bool],
+    ) -> bool {
+        match self.right[right] {
+            None => true,
+            Some(next) if !visited[next] => {
+                visited[next] = true;
+                adj[next].iter().any(|right| {
+                    *right == target || self.alternating_path(adj, *right, target, visited)
+                })
+            }
+            Some(_) => false,
+        }
     }
 }
 
@@ -186,14 +478,198 @@ fn main() {
         .expect("input should parse correctly");
     println!("Part 1: {}", input.find_invalid_fields().sum::<usize>());
 
-    input.discard_invalid_tickets();
-    println!(
-        "Part 2: {}",
+    let reports = input.discard_invalid_tickets();
+    if std::env::args().any(|arg| arg == "--report") {
+        for report in reports {
+            println!("{}", report);
+        }
+        return;
+    }
+    match input.figure_out_rules() {
+        Ok(rules) => println!(
+            "Part 2: {}",
+            input
+                .your_ticket
+                .iter()
+                .zip(rules.iter())
+                .filter_map(|(val, rule)| if rule.is_departure() { Some(val) } else { None })
+                .product::<usize>()
+        ),
+        Err(err) => eprintln!("Part 2: {}", err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule_names(input: &Input) -> Result<Vec<&str>, AssignmentError> {
         input
-            .your_ticket
-            .iter()
-            .zip(input.figure_out_rules().iter())
-            .filter_map(|(val, rule)| if rule.is_departure() { Some(val) } else { None })
-            .product::<usize>()
-    );
+            .figure_out_rules()
+            .map(|rules| rules.iter().map(|rule| rule.name.as_str()).collect())
+    }
+
+    #[test]
+    fn test_part2_sample() {
+        let input = "class: 0-1 or 4-19
+row: 0-5 or 8-19
+seat: 0-13 or 16-19
+
+your ticket:
+11,12,13
+
+nearby tickets:
+3,9,18
+15,1,5
+5,14,9"
+            .parse::<Input>()
+            .unwrap();
+        assert_eq!(rule_names(&input), Ok(vec!["row", "class", "seat"]));
+    }
+
+    #[test]
+    fn test_chain_of_candidates() {
+        // only position 2 has a single candidate, every other one has two and is settled
+        // by its neighbour in the chain a, b, c, d (a unique assignment always has at
+        // least one position with a single candidate)
+        let input = "a: 1-2
+b: 2-4
+c: 4-6
+d: 6-7
+
+your ticket:
+6,2,1,4
+
+nearby tickets:
+6,2,1,4
+6,2,1,4"
+            .parse::<Input>()
+            .unwrap();
+        assert_eq!(rule_names(&input), Ok(vec!["d", "b", "a", "c"]));
+    }
+
+    #[test]
+    fn test_ambiguous() {
+        let input = "a: 1-5
+b: 1-5
+c: 7-7
+
+your ticket:
+1,7,2
+
+nearby tickets:
+3,7,4"
+            .parse::<Input>()
+            .unwrap();
+        assert_eq!(
+            rule_names(&input),
+            Err(AssignmentError::Ambiguous {
+                fields: vec!["a".to_owned(), "b".to_owned()]
+            })
+        );
+    }
+
+    #[test]
+    fn test_impossible() {
+        let input = "a: 1-5
+b: 1-5
+c: 7-7
+
+your ticket:
+1,2,3
+
+nearby tickets:
+3,3,4"
+            .parse::<Input>()
+            .unwrap();
+        let err = rule_names(&input).unwrap_err();
+        assert_eq!(
+            err,
+            AssignmentError::Impossible {
+                positions: vec![0, 1, 2],
+                fields: vec!["a".to_owned(), "b".to_owned()]
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "no valid assignment, the 3 positions 0, 1, 2 only fit the 2 fields a, b"
+        );
+
+        // positions 0 and 1 are fine on their own, the conflict is between 2 and 3
+        let input = "a: 1-1
+b: 2-2
+c: 3-9
+d: 5-9
+
+your ticket:
+1,2,3,4
+
+nearby tickets:
+1,2,3,3"
+            .parse::<Input>()
+            .unwrap();
+        assert_eq!(
+            rule_names(&input),
+            Err(AssignmentError::Impossible {
+                positions: vec![2, 3],
+                fields: vec!["c".to_owned()]
+            })
+        );
+    }
+
+    #[test]
+    fn test_rule_ranges() {
+        let rule = "odd: 1 or 3-3 or 5 or 7-9 or 11".parse::<Rule>().unwrap();
+        assert_eq!(rule.ranges, vec![1..=1, 3..=3, 5..=5, 7..=9, 11..=11]);
+        assert!(rule.in_range(&8));
+        assert!(!rule.in_range(&10));
+        assert_eq!(rule.closest_range(10), Some((&(7..=9), 1)));
+        assert!("bad: 5-3".parse::<Rule>().is_err());
+        assert!("bad:".parse::<Rule>().is_err());
+        assert!("bad: 1-2 or".parse::<Rule>().is_err());
+    }
+
+    #[test]
+    fn test_validation_report() {
+        let mut input = "class: 1-3 or 5-7
+row: 6-11 or 33-44
+seat: 13-40 or 45-50
+
+your ticket:
+7,1,14
+
+nearby tickets:
+7,3,47
+40,4,50
+55,2,20
+38,6,12"
+            .parse::<Input>()
+            .unwrap();
+        assert_eq!(input.find_invalid_fields().sum::<usize>(), 71);
+        let reports = input.discard_invalid_tickets();
+        assert_eq!(input.nearby_tickets.len(), 1);
+        assert_eq!(
+            reports
+                .iter()
+                .map(|report| report.ticket)
+                .collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+        assert_eq!(
+            reports[1].invalid,
+            vec![InvalidValue {
+                position: 0,
+                value: 55,
+                closest: Some(ClosestMiss {
+                    rule: "seat".to_owned(),
+                    range: 45..=50,
+                    distance: 5
+                })
+            }]
+        );
+        assert_eq!(
+            reports[0].to_string(),
+            "nearby ticket 1 rejected:\n    value 4 at position 1, closest is class 1-3 (off by 1)"
+        );
+    }
 }
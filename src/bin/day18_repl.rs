@@ -0,0 +1,168 @@
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+use advent_of_code_2020::homework::{self, underline, Arith, Precedence};
+use num::BigInt;
+
+const PROMPT: &str = "> ";
+
+const HELP: &str = "\
+expressions use + - * / % ^, parentheses and $n for the n-th result
+:mode          show the precedence mode
+:mode <mode>   switch to same, add-first, math or a table like `+ 2 left, * 1 left`
+:history       list the results so far
+:quit          leave";
+
+struct Repl<T> {
+    mode: String,
+    precedence: Precedence,
+    results: Vec<T>,
+}
+
+impl<T: Arith + fmt::Display> Repl<T> {
+    fn new(mode: &str) -> Result<Self, String> {
+        Ok(Self {
+            mode: mode.to_owned(),
+            precedence: parse_mode(mode)?,
+            results: Vec::new(),
+        })
+    }
+
+    /// Output for one input line, `None` once the session should end.
+    fn handle(&mut self, line: &str) -> Option<String> {
+        let line = line.trim_end();
+        let reply = match line.trim_start().strip_prefix(':') {
+            Some(command) => {
+                let (name, arg) = command.split_once(' ').unwrap_or((command, ""));
+                match (name, arg.trim()) {
+                    ("q" | "quit", _) => return None,
+                    ("help", _) => HELP.to_owned(),
+                    ("history", _) => self.history(),
+                    ("mode", "") => format!("mode: {}", self.mode),
+                    ("mode", mode) => match parse_mode(mode) {
+                        Ok(precedence) => {
+                            self.mode = mode.to_owned();
+                            self.precedence = precedence;
+                            format!("mode: {}", self.mode)
+                        }
+                        Err(err) => err,
+                    },
+                    _ => format!("unknown command :{}, try :help", name),
+                }
+            }
+            None if line.trim().is_empty() => String::new(),
+            None => self.eval(line),
+        };
+        Some(reply)
+    }
+
+    fn eval(&mut self, line: &str) -> String {
+        let expr = match homework::parse(line, &self.precedence) {
+            Ok(expr) => expr,
+            Err(err) => {
+                // the carets line up with the input after the prompt
+                return format!(
+                    "{}{}\nerror: {}",
+                    " ".repeat(PROMPT.len()),
+                    underline(line, err.span.clone()),
+                    err
+                );
+            }
+        };
+        match expr.eval_with(&self.results) {
+            Ok(value) => {
+                let reply = format!("{}\n${} = {}", expr, self.results.len() + 1, value);
+                self.results.push(value);
+                reply
+            }
+            Err(err) => format!("{}\nerror: {}", expr, err),
+        }
+    }
+
+    fn history(&self) -> String {
+        let lines: Vec<String> = self
+            .results
+            .iter()
+            .enumerate()
+            .map(|(idx, value)| format!("${} = {}", idx + 1, value))
+            .collect();
+        lines.join("\n")
+    }
+}
+
+fn parse_mode(mode: &str) -> Result<Precedence, String> {
+    mode.parse().map_err(|_| {
+        format!(
+            "unknown mode `{}`, expected same, add-first, math or a table like `+ 2 left, * 1 left`",
+            mode
+        )
+    })
+}
+
+fn run<T: Arith + fmt::Display>(mode: &str) -> io::Result<()> {
+    let mut repl = Repl::<T>::new(mode).map_err(io::Error::other)?;
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+    write!(stdout, "{}", PROMPT)?;
+    stdout.flush()?;
+    for line in stdin.lock().lines() {
+        match repl.handle(&line?) {
+            Some(reply) if reply.is_empty() => {}
+            Some(reply) => writeln!(stdout, "{}", reply)?,
+            None => return Ok(()),
+        }
+        write!(stdout, "{}", PROMPT)?;
+        stdout.flush()?;
+    }
+    writeln!(stdout)
+}
+
+fn main() -> io::Result<()> {
+    // usage: day18_repl [--big] [mode]
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mode = args
+        .iter()
+        .find(|arg| !arg.starts_with("--"))
+        .map_or("same", |arg| arg.as_str());
+    if args.iter().any(|arg| arg == "--big") {
+        run::<BigInt>(mode)
+    } else {
+        run::<i64>(mode)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session() {
+        let mut repl = Repl::<i64>::new("same").unwrap();
+        let mut send = |line| repl.handle(line).unwrap();
+        assert_eq!(send("1 + 2 * 3"), "((1 + 2) * 3)\n$1 = 9");
+        assert_eq!(send(":mode math"), "mode: math");
+        assert_eq!(send("1 + 2 * 3"), "(1 + (2 * 3))\n$2 = 7");
+        assert_eq!(send("$1 - $2"), "($1 - $2)\n$3 = 2");
+        assert_eq!(send("$4"), "$4\nerror: there is no result $4");
+        assert_eq!(send("1 / 0"), "(1 / 0)\nerror: division by zero");
+        assert_eq!(
+            send("(1 + 2"),
+            "  ^\nerror: unclosed '(' opened at column 1"
+        );
+        assert_eq!(send(":history"), "$1 = 9\n$2 = 7\n$3 = 2");
+        assert_eq!(send(":mode"), "mode: math");
+        assert!(send(":mode backwards").starts_with("unknown mode `backwards`"));
+        assert_eq!(send(":mode"), "mode: math");
+        assert_eq!(send(""), "");
+        assert_eq!(repl.handle(":quit"), None);
+    }
+
+    #[test]
+    fn test_custom_mode() {
+        let mut repl = Repl::<BigInt>::new("+ 1 left, * 2 left").unwrap();
+        assert_eq!(repl.handle("2 * 3 + 1").unwrap(), "((2 * 3) + 1)\n$1 = 7");
+        assert_eq!(repl.handle(":mode + 255, * 0").unwrap(), "mode: + 255, * 0");
+        assert_eq!(repl.handle("2 * 3 + 1").unwrap(), "(2 * (3 + 1))\n$2 = 8");
+        assert!(Repl::<i64>::new("nonsense").is_err());
+    }
+}
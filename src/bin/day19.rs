@@ -1,173 +1,881 @@
-use std::{collections::HashMap, str::FromStr};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
 
-#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
-enum Char {
-    A,
-    B,
-}
+use num::BigUint;
+use regex::{Regex, RegexBuilder};
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 enum Expr {
     Or(Vec<Expr>),
     Concat(Vec<Expr>),
-    Const(Char),
+    /// quoted literal, may be empty or longer than one char
+    Const(String),
     Ref(usize),
 }
 
-impl Expr {
-    fn matched_len(&self, msg: &[Char], exprs: &HashMap<usize, Expr>) -> Option<usize> {
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+enum Symbol {
+    /// index into the grammar's literals
+    Term(usize),
+    Rule(usize),
+}
+
+/// Position in an alternative of a rule, the Earley item `rule -> alt[..dot] . alt[dot..]`
+/// started at `origin`.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+struct Item {
+    rule: usize,
+    alt: usize,
+    dot: usize,
+    origin: usize,
+}
+
+/// Frame of a walk through a non-recursive rule: the next symbol of an alternative and
+/// how many chars of it are read.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+struct Frame {
+    rule: usize,
+    alt: usize,
+    dot: usize,
+    read: usize,
+}
+
+/// Frames from the outermost rule in, empty once the whole rule is read.
+type Walk = Vec<Frame>;
+
+/// Walks that read the same prefix, a state of the DFA of a finite language.
+type WalkSet = BTreeSet<Walk>;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum GrammarError {
+    UnknownRule(usize),
+}
+
+#[derive(Debug)]
+enum CompileError {
+    /// the rule can reach itself, so its language may be infinite
+    Recursive(usize),
+    Regex(regex::Error),
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Expr::Or(sub_exprs) => sub_exprs
-                .iter()
-                .map(|expr| expr.matched_len(msg, exprs))
-                .find(Option::is_some)
-                .flatten(),
-            Expr::Concat(sub_exprs) => {
-                let mut len = 0;
-                let mut msg = msg;
-                for expr in sub_exprs {
-                    if let Some(matched) = expr.matched_len(msg, exprs) {
-                        len += matched;
-                        msg = msg.split_at(matched).1;
-                    } else {
-                        return None;
+            CompileError::Recursive(rule) => write!(f, "rule {} is recursive", rule),
+            CompileError::Regex(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct InvalidMessage {
+    message: String,
+    c: char,
+    /// 1-based, in chars
+    column: usize,
+}
+
+impl fmt::Display for InvalidMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unknown character {:?} at column {} of {:?}",
+            self.c, self.column, self.message
+        )
+    }
+}
+
+/// The rules flattened into alternatives of symbols, recognized with an Earley parser
+/// so any recursion (left recursion included) terminates.
+#[derive(Clone, Debug)]
+struct Grammar {
+    rules: HashMap<usize, Vec<Vec<Symbol>>>,
+    literals: Vec<Vec<char>>,
+    /// every char used by some literal
+    alphabet: HashSet<char>,
+    nullable: HashSet<usize>,
+}
+
+impl Grammar {
+    fn new(exprs: &HashMap<usize, Expr>) -> Result<Self, GrammarError> {
+        let mut literals = Vec::new();
+        let rules: HashMap<usize, Vec<Vec<Symbol>>> = exprs
+            .iter()
+            .map(|(idx, expr)| (*idx, alternatives(expr, &mut literals)))
+            .collect();
+        for alts in rules.values() {
+            for symbol in alts.iter().flatten() {
+                match symbol {
+                    Symbol::Rule(idx) if !rules.contains_key(idx) => {
+                        return Err(GrammarError::UnknownRule(*idx))
                     }
+                    _ => {}
                 }
-                Some(len)
             }
-            Expr::Const(c) => {
-                if msg.first().map_or(false, |msg_c| c == msg_c) {
-                    Some(1)
+        }
+        let mut nullable = HashSet::new();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (idx, alts) in &rules {
+                if !nullable.contains(idx)
+                    && alts.iter().any(|alt| {
+                        alt.iter().all(|symbol| match symbol {
+                            Symbol::Term(lit) => literals[*lit].is_empty(),
+                            Symbol::Rule(rule) => nullable.contains(rule),
+                        })
+                    })
+                {
+                    nullable.insert(*idx);
+                    changed = true;
+                }
+            }
+        }
+        Ok(Self {
+            rules,
+            alphabet: literals.iter().flatten().copied().collect(),
+            literals,
+            nullable,
+        })
+    }
+
+    /// The chars of `message`, as long as the rules could produce every one of them.
+    fn parse_message(&self, message: &str) -> Result<Vec<char>, InvalidMessage> {
+        message
+            .chars()
+            .enumerate()
+            .map(|(idx, c)| {
+                if self.alphabet.contains(&c) {
+                    Ok(c)
                 } else {
-                    None
+                    Err(InvalidMessage {
+                        message: message.to_owned(),
+                        c,
+                        column: idx + 1,
+                    })
                 }
+            })
+            .collect()
+    }
+
+    fn next_symbol(&self, item: &Item) -> Option<Symbol> {
+        self.rules[&item.rule][item.alt].get(item.dot).copied()
+    }
+
+    /// Earley sets, `chart[pos]` holds the items reached after `pos` chars of `msg`.
+    fn chart(&self, start: usize, msg: &[char]) -> Vec<Vec<Item>> {
+        let mut chart: Vec<Vec<Item>> = vec![Vec::new(); msg.len() + 1];
+        let mut seen: Vec<HashSet<Item>> = vec![HashSet::new(); msg.len() + 1];
+        let mut add = |chart: &mut Vec<Vec<Item>>, pos: usize, item: Item| {
+            if seen[pos].insert(item) {
+                chart[pos].push(item);
             }
-            Expr::Ref(idx) => exprs.get(idx).and_then(|expr| expr.matched_len(msg, exprs)),
+        };
+        for alt in 0..self.rules.get(&start).map_or(0, Vec::len) {
+            let item = Item {
+                rule: start,
+                alt,
+                dot: 0,
+                origin: 0,
+            };
+            add(&mut chart, 0, item);
         }
+        for pos in 0..=msg.len() {
+            let mut idx = 0;
+            while let Some(item) = chart[pos].get(idx).copied() {
+                idx += 1;
+                let advanced = Item {
+                    dot: item.dot + 1,
+                    ..item
+                };
+                match self.next_symbol(&item) {
+                    Some(Symbol::Term(lit)) => {
+                        let literal = &self.literals[lit];
+                        if msg[pos..].starts_with(literal) {
+                            add(&mut chart, pos + literal.len(), advanced);
+                        }
+                    }
+                    Some(Symbol::Rule(rule)) => {
+                        for alt in 0..self.rules[&rule].len() {
+                            let predicted = Item {
+                                rule,
+                                alt,
+                                dot: 0,
+                                origin: pos,
+                            };
+                            add(&mut chart, pos, predicted);
+                        }
+                        // completions of an empty match were already processed
+                        if self.nullable.contains(&rule) {
+                            add(&mut chart, pos, advanced);
+                        }
+                    }
+                    None => {
+                        let parents: Vec<Item> = chart[item.origin]
+                            .iter()
+                            .filter(|parent| {
+                                self.next_symbol(parent) == Some(Symbol::Rule(item.rule))
+                            })
+                            .map(|parent| Item {
+                                dot: parent.dot + 1,
+                                ..*parent
+                            })
+                            .collect();
+                        for parent in parents {
+                            add(&mut chart, pos, parent);
+                        }
+                    }
+                }
+            }
+        }
+        chart
     }
 
-    fn matched_consec_lens(
-        con_exprs: &[Expr],
-        msg: &[Char],
-        exprs: &HashMap<usize, Expr>,
-    ) -> Vec<usize> {
-        let mut results = Vec::new();
-        if let Some((first, rest)) = con_exprs.split_first() {
-            for first_len in first.matched_lens(msg, exprs) {
-                results.extend(
-                    Expr::matched_consec_lens(rest, msg.split_at(first_len).1, exprs)
-                        .iter()
-                        .map(|rest_len| first_len + rest_len),
-                );
+    /// Lengths of the prefixes of `msg` that rule `start` matches, in increasing order.
+    fn matched_lens(&self, start: usize, msg: &[char]) -> Vec<usize> {
+        let chart = self.chart(start, msg);
+        (0..=msg.len())
+            .filter(|pos| {
+                chart[*pos].iter().any(|item| {
+                    item.rule == start && item.origin == 0 && self.next_symbol(item).is_none()
+                })
+            })
+            .collect()
+    }
+
+    fn matches(&self, start: usize, msg: &[char]) -> bool {
+        self.matched_lens(start, msg).last() == Some(&msg.len())
+    }
+
+    /// Evaluates rule `rule` bottom up, `memo` holds `None` for the rules being evaluated
+    /// so recursion is detected instead of followed.
+    fn fold<T: Clone>(
+        &self,
+        rule: usize,
+        memo: &mut HashMap<usize, Option<T>>,
+        literal: &dyn Fn(&[char]) -> T,
+        concat: &dyn Fn(Vec<T>) -> T,
+        union: &dyn Fn(Vec<T>) -> T,
+    ) -> Result<T, CompileError> {
+        match memo.get(&rule) {
+            Some(Some(done)) => return Ok(done.clone()),
+            Some(None) => return Err(CompileError::Recursive(rule)),
+            None => {}
+        }
+        memo.insert(rule, None);
+        let mut alts = Vec::new();
+        for alt in &self.rules[&rule] {
+            let mut parts = Vec::new();
+            for symbol in alt {
+                parts.push(match symbol {
+                    Symbol::Term(lit) => literal(&self.literals[*lit]),
+                    Symbol::Rule(sub) => self.fold(*sub, memo, literal, concat, union)?,
+                });
+            }
+            alts.push(concat(parts));
+        }
+        let result = union(alts);
+        memo.insert(rule, Some(result.clone()));
+        Ok(result)
+    }
+
+    /// An anchored regex equivalent to a non-recursive rule.
+    fn to_regex(&self, start: usize) -> Result<Regex, CompileError> {
+        let pattern = self.fold(
+            start,
+            &mut HashMap::new(),
+            &|literal| regex::escape(&literal.iter().collect::<String>()),
+            &|parts| parts.concat(),
+            &|alts| match alts.as_slice() {
+                [alt] => alt.clone(),
+                _ => format!("(?:{})", alts.join("|")),
+            },
+        )?;
+        RegexBuilder::new(&format!("^{}$", pattern))
+            .size_limit(1 << 26)
+            .build()
+            .map_err(CompileError::Regex)
+    }
+
+    /// Walks through a non-recursive rule that haven't read anything yet.
+    fn start_walks(&self, start: usize) -> Result<WalkSet, CompileError> {
+        self.fold(start, &mut HashMap::new(), &|_| (), &|_| (), &|_| ())?;
+        let mut walks = WalkSet::new();
+        for alt in 0..self.rules[&start].len() {
+            let frame = Frame {
+                rule: start,
+                alt,
+                dot: 0,
+                read: 0,
+            };
+            self.settle(vec![frame], &mut walks);
+        }
+        Ok(walks)
+    }
+
+    /// Moves `walk` past finished symbols and into rules (one walk per alternative) until
+    /// it waits for a char, adds the results to `walks`.
+    fn settle(&self, mut walk: Walk, walks: &mut WalkSet) {
+        while let Some(frame) = walk.last_mut() {
+            match self.rules[&frame.rule][frame.alt].get(frame.dot) {
+                None => {
+                    walk.pop();
+                    if let Some(parent) = walk.last_mut() {
+                        parent.dot += 1;
+                    }
+                }
+                Some(Symbol::Term(lit)) if frame.read < self.literals[*lit].len() => break,
+                Some(Symbol::Term(_)) => {
+                    frame.dot += 1;
+                    frame.read = 0;
+                }
+                Some(Symbol::Rule(rule)) => {
+                    for alt in 0..self.rules[rule].len() {
+                        let mut inner = walk.clone();
+                        inner.push(Frame {
+                            rule: *rule,
+                            alt,
+                            dot: 0,
+                            read: 0,
+                        });
+                        self.settle(inner, walks);
+                    }
+                    return;
+                }
             }
+        }
+        walks.insert(walk);
+    }
+
+    /// The DFA transitions out of `walks`, by char.
+    fn step_walks(&self, walks: &WalkSet) -> BTreeMap<char, WalkSet> {
+        let mut next: BTreeMap<char, WalkSet> = BTreeMap::new();
+        for walk in walks {
+            // settled walks are either done or wait in a literal
+            let Some(frame) = walk.last() else {
+                continue;
+            };
+            let Symbol::Term(lit) = self.rules[&frame.rule][frame.alt][frame.dot] else {
+                continue;
+            };
+            let c = self.literals[lit][frame.read];
+            let mut advanced = walk.clone();
+            advanced.last_mut().expect("checked above").read += 1;
+            self.settle(advanced, next.entry(c).or_default());
+        }
+        next
+    }
+
+    /// Number of distinct strings a non-recursive rule matches, without building any of
+    /// them: every string takes exactly one path through the DFA.
+    fn count_strings(&self, start: usize) -> Result<BigUint, CompileError> {
+        fn paths(
+            grammar: &Grammar,
+            walks: &WalkSet,
+            memo: &mut HashMap<WalkSet, BigUint>,
+        ) -> BigUint {
+            if let Some(count) = memo.get(walks) {
+                return count.clone();
+            }
+            let mut count = BigUint::from(walks.contains(&Walk::new()) as u8);
+            for next in grammar.step_walks(walks).values() {
+                count += paths(grammar, next, memo);
+            }
+            memo.insert(walks.clone(), count.clone());
+            count
+        }
+        Ok(paths(self, &self.start_walks(start)?, &mut HashMap::new()))
+    }
+
+    /// The distinct strings a non-recursive rule matches in sorted order, found one at a
+    /// time so taking the first few stays cheap.
+    fn language(&self, start: usize) -> Result<Language<'_>, CompileError> {
+        Ok(Language {
+            grammar: self,
+            pending: vec![(String::new(), self.start_walks(start)?)],
+        })
+    }
+
+    /// A derivation tree if rule `start` matches all of `msg`, otherwise where it got stuck.
+    fn explain(&self, start: usize, msg: &[char]) -> Explanation {
+        let chart = self.chart(start, msg);
+        let is_done = |item: &Item| self.next_symbol(item).is_none();
+        let items: Vec<HashSet<Item>> = chart
+            .iter()
+            .map(|items| items.iter().copied().collect())
+            .collect();
+        let completed: Vec<HashSet<(usize, usize)>> = chart
+            .iter()
+            .map(|items| {
+                items
+                    .iter()
+                    .filter(|item| is_done(item))
+                    .map(|item| (item.rule, item.origin))
+                    .collect()
+            })
+            .collect();
+        if completed[msg.len()].contains(&(start, 0)) {
+            let mut deriver = Deriver {
+                grammar: self,
+                msg,
+                items,
+                completed,
+                visiting: HashSet::new(),
+            };
+            if let Some(tree) = deriver.rule(start, 0, msg.len()) {
+                return Explanation::Match(tree);
+            }
+        }
+        let matched = (0..=msg.len())
+            .rev()
+            .find(|pos| !chart[*pos].is_empty())
+            .unwrap_or_default();
+        let mut expected: Vec<(usize, String)> = chart[matched]
+            .iter()
+            .filter_map(|item| match self.next_symbol(item) {
+                Some(Symbol::Term(lit)) if !self.literals[lit].is_empty() => {
+                    Some((item.rule, self.literals[lit].iter().collect()))
+                }
+                _ => None,
+            })
+            .collect();
+        expected.sort_unstable();
+        expected.dedup();
+        Explanation::NoMatch(Failure {
+            matched,
+            found: msg.get(matched).copied(),
+            expected,
+            end_allowed: chart[matched]
+                .iter()
+                .any(|item| item.rule == start && item.origin == 0 && is_done(item)),
+        })
+    }
+}
+
+/// Strings of a finite language, depth first through its DFA with the smallest char first.
+struct Language<'a> {
+    grammar: &'a Grammar,
+    /// prefixes still to visit with the state they lead to, the smallest on top
+    pending: Vec<(String, WalkSet)>,
+}
+
+impl Iterator for Language<'_> {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        while let Some((prefix, walks)) = self.pending.pop() {
+            for (c, next) in self.grammar.step_walks(&walks).into_iter().rev() {
+                self.pending.push((format!("{}{}", prefix, c), next));
+            }
+            if walks.contains(&Walk::new()) {
+                return Some(prefix);
+            }
+        }
+        None
+    }
+}
+
+/// Rebuilds a derivation from the sets of an Earley chart.
+struct Deriver<'a> {
+    grammar: &'a Grammar,
+    msg: &'a [char],
+    items: Vec<HashSet<Item>>,
+    /// (rule, origin) of the items completed at each position
+    completed: Vec<HashSet<(usize, usize)>>,
+    /// (rule, start, end) being derived, so cycles like `0: 0 | 1` are cut off
+    visiting: HashSet<(usize, usize, usize)>,
+}
+
+impl<'a> Deriver<'a> {
+    fn rule(&mut self, rule: usize, start: usize, end: usize) -> Option<Tree> {
+        if !self.visiting.insert((rule, start, end)) {
+            return None;
+        }
+        let alts = self.grammar.rules[&rule].len();
+        let children = (0..alts).find_map(|alt| {
+            let dot = self.grammar.rules[&rule][alt].len();
+            let item = Item {
+                rule,
+                alt,
+                dot,
+                origin: start,
+            };
+            if self.items[end].contains(&item) {
+                self.alt(item, end)
+            } else {
+                None
+            }
+        });
+        self.visiting.remove(&(rule, start, end));
+        Some(Tree::Rule {
+            rule,
+            children: children?,
+        })
+    }
+
+    /// Children for the symbols before the dot of `item`, which ends at `end`.
+    fn alt(&mut self, item: Item, end: usize) -> Option<Vec<Tree>> {
+        if item.dot == 0 {
+            return (item.origin == end).then(Vec::new);
+        }
+        let before = Item {
+            dot: item.dot - 1,
+            ..item
+        };
+        match self.grammar.rules[&item.rule][item.alt][before.dot] {
+            Symbol::Term(lit) => {
+                let literal = &self.grammar.literals[lit];
+                let begin = end.checked_sub(literal.len())?;
+                if self.msg[begin..end] != literal[..] || !self.items[begin].contains(&before) {
+                    return None;
+                }
+                let mut children = self.alt(before, begin)?;
+                children.push(Tree::Term(literal.iter().collect()));
+                Some(children)
+            }
+            Symbol::Rule(rule) => (item.origin..=end).find_map(|mid| {
+                if !self.items[mid].contains(&before) || !self.completed[end].contains(&(rule, mid))
+                {
+                    return None;
+                }
+                let child = self.rule(rule, mid, end)?;
+                let mut children = self.alt(before, mid)?;
+                children.push(child);
+                Some(children)
+            }),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Tree {
+    Term(String),
+    Rule { rule: usize, children: Vec<Tree> },
+}
+
+impl Tree {
+    fn write_indented(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+        match self {
+            Tree::Term(literal) => writeln!(f, "{:indent$}{:?}", "", literal, indent = depth * 2),
+            Tree::Rule { rule, children } => {
+                writeln!(f, "{:indent$}{}", "", rule, indent = depth * 2)?;
+                for child in children {
+                    child.write_indented(f, depth + 1)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn to_json(&self) -> String {
+        match self {
+            Tree::Term(literal) => json_string(literal),
+            Tree::Rule { rule, children } => {
+                let children: Vec<String> = children.iter().map(Tree::to_json).collect();
+                format!(
+                    "{{\"rule\":{},\"children\":[{}]}}",
+                    rule,
+                    children.join(",")
+                )
+            }
+        }
+    }
+}
+
+/// One node per line, children indented below their rule.
+impl fmt::Display for Tree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write_indented(f, 0)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Failure {
+    /// length of the longest prefix some message of the language starts with
+    matched: usize,
+    /// char after that prefix, `None` if the message ended
+    found: Option<char>,
+    /// (rule, literal) pairs that could have continued the prefix
+    expected: Vec<(usize, String)>,
+    /// whether the start rule could have ended after the prefix
+    end_allowed: bool,
+}
+
+impl Failure {
+    fn to_json(&self) -> String {
+        let expected: Vec<String> = self
+            .expected
+            .iter()
+            .map(|(rule, literal)| {
+                format!("{{\"rule\":{},\"term\":{}}}", rule, json_string(literal))
+            })
+            .collect();
+        format!(
+            "{{\"matched\":{},\"found\":{},\"expected\":[{}],\"end_allowed\":{}}}",
+            self.matched,
+            self.found
+                .map_or("null".to_owned(), |c| json_string(&c.to_string())),
+            expected.join(","),
+            self.end_allowed
+        )
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "no match after {} char(s), found ", self.matched)?;
+        match self.found {
+            Some(c) => write!(f, "{:?}", c.to_string())?,
+            None => write!(f, "end of message")?,
+        }
+        let mut expected: Vec<String> = self
+            .expected
+            .iter()
+            .map(|(rule, literal)| format!("{:?} (rule {})", literal, rule))
+            .collect();
+        if self.end_allowed {
+            expected.push("end of message".to_owned());
+        }
+        if expected.is_empty() {
+            write!(f, ", expected nothing")
         } else {
-            results.push(0);
+            write!(f, ", expected {}", expected.join(" or "))
         }
-        results
     }
+}
 
-    fn matched_lens(&self, msg: &[Char], exprs: &HashMap<usize, Expr>) -> Vec<usize> {
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Explanation {
+    Match(Tree),
+    NoMatch(Failure),
+}
+
+impl Explanation {
+    fn to_json(&self) -> String {
+        match self {
+            Explanation::Match(tree) => format!("{{\"match\":true,\"tree\":{}}}", tree.to_json()),
+            Explanation::NoMatch(failure) => {
+                format!("{{\"match\":false,\"failure\":{}}}", failure.to_json())
+            }
+        }
+    }
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Expr::Or(sub_exprs) => sub_exprs
+            Explanation::Match(tree) => write!(f, "{}", tree),
+            Explanation::NoMatch(failure) => writeln!(f, "{}", failure),
+        }
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut json = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+/// Every way to spell out `expr` as a sequence of symbols, literals are interned into
+/// `literals`.
+fn alternatives(expr: &Expr, literals: &mut Vec<Vec<char>>) -> Vec<Vec<Symbol>> {
+    match expr {
+        Expr::Or(sub_exprs) => sub_exprs
+            .iter()
+            .flat_map(|sub| alternatives(sub, literals))
+            .collect(),
+        Expr::Concat(sub_exprs) => sub_exprs.iter().fold(vec![vec![]], |prefixes, sub| {
+            let suffixes = alternatives(sub, literals);
+            prefixes
                 .iter()
-                .flat_map(|expr| expr.matched_lens(msg, exprs))
-                .collect(),
-            Expr::Concat(sub_exprs) => Expr::matched_consec_lens(sub_exprs, msg, exprs),
-            Expr::Const(c) => {
-                if msg.first().map_or(false, |msg_c| c == msg_c) {
-                    vec![1]
-                } else {
-                    vec![]
+                .flat_map(|prefix| {
+                    suffixes
+                        .iter()
+                        .map(move |suffix| [prefix.as_slice(), suffix].concat())
+                })
+                .collect()
+        }),
+        Expr::Const(literal) => {
+            let chars: Vec<char> = literal.chars().collect();
+            let lit = match literals.iter().position(|known| *known == chars) {
+                Some(lit) => lit,
+                None => {
+                    literals.push(chars);
+                    literals.len() - 1
                 }
-            }
-            Expr::Ref(idx) => exprs[idx].matched_lens(msg, exprs),
+            };
+            vec![vec![Symbol::Term(lit)]]
         }
+        Expr::Ref(idx) => vec![vec![Symbol::Rule(*idx)]],
     }
 }
 
+/// Alternatives separated by `|`, each a sequence of rule numbers and quoted literals in
+/// which `\"` and `\\` escape.
 impl FromStr for Expr {
     type Err = ();
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "\"a\"" => Ok(Expr::Const(Char::A)),
-            "\"b\"" => Ok(Expr::Const(Char::B)),
-            _ if s.contains('|') => Ok(Expr::Or(
-                s.split('|')
-                    .map(|sub| sub.trim().parse::<Expr>())
-                    .collect::<Result<Vec<_>, _>>()?,
-            )),
-            _ => Ok(Expr::Concat(
-                s.split(' ')
-                    .map(|sub| sub.parse::<usize>().map(Expr::Ref))
-                    .collect::<Result<Vec<_>, _>>()
-                    .map_err(|_| ())?,
-            )),
+        let mut seqs: Vec<Vec<Expr>> = vec![Vec::new()];
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            let seq = seqs.last_mut().ok_or(())?;
+            match c {
+                '|' => seqs.push(Vec::new()),
+                '"' => {
+                    let mut literal = String::new();
+                    loop {
+                        match chars.next().ok_or(())? {
+                            '"' => break,
+                            '\\' => literal.push(chars.next().ok_or(())?),
+                            c => literal.push(c),
+                        }
+                    }
+                    seq.push(Expr::Const(literal));
+                }
+                c if c.is_ascii_digit() => {
+                    let mut idx = c.to_string();
+                    while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                        idx.push(digit);
+                    }
+                    seq.push(Expr::Ref(idx.parse().map_err(|_| ())?));
+                }
+                c if c.is_whitespace() => {}
+                _ => return Err(()),
+            }
+        }
+        let mut alts = seqs
+            .into_iter()
+            .map(|mut seq| match seq.as_slice() {
+                [] => Err(()),
+                [Expr::Const(_)] => seq.pop().ok_or(()),
+                _ => Ok(Expr::Concat(seq)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if alts.len() == 1 {
+            alts.pop().ok_or(())
+        } else {
+            Ok(Expr::Or(alts))
         }
     }
 }
 
-fn parse_input(input: &str) -> (HashMap<usize, Expr>, Vec<Vec<Char>>) {
+/// Rules in the puzzle's own syntax, used for part 2 unless `--overrides` names a file.
+const PART2_OVERRIDES: &str = "8: 42 | 42 8\n11: 42 31 | 42 11 31";
+
+fn parse_rules<'a>(lines: impl Iterator<Item = &'a str>) -> Result<HashMap<usize, Expr>, ()> {
     let mut exprs = HashMap::new();
+    for line in lines {
+        let (idx, rest) = line.split_once(':').ok_or(())?;
+        let idx = idx.trim().parse::<usize>().map_err(|_| ())?;
+        exprs.insert(idx, rest.trim().parse::<Expr>()?);
+    }
+    Ok(exprs)
+}
+
+fn parse_input(input: &str) -> (HashMap<usize, Expr>, Vec<&str>) {
     let mut lines = input.lines();
+    let exprs = parse_rules(lines.by_ref().take_while(|line| !line.is_empty()))
+        .expect("rules should parse");
+    (exprs, lines.collect())
+}
 
-    for line in lines.by_ref() {
-        if line.is_empty() {
-            break;
+fn count_matches(exprs: &HashMap<usize, Expr>, messages: &[&str]) -> Result<usize, InvalidMessage> {
+    let grammar = Grammar::new(exprs).expect("rules should only refer to existing rules");
+    // without recursion the rules are a regular language
+    let regex = grammar.to_regex(0).ok();
+    let mut count = 0;
+    for message in messages {
+        let msg = grammar.parse_message(message)?;
+        let matched = match &regex {
+            Some(regex) => regex.is_match(message),
+            None => grammar.matches(0, &msg),
+        };
+        if matched {
+            count += 1;
         }
-        let mut parts = line.split(':');
-        let idx = parts.next().unwrap().parse::<usize>().unwrap();
-        let rest = parts.next().unwrap().trim();
-        let expr = rest.parse::<Expr>().expect("should parse");
-        exprs.insert(idx, expr);
     }
+    Ok(count)
+}
 
-    let messages = lines
-        .map(|line| {
-            line.chars()
-                .map(|c| if c == 'a' { Char::A } else { Char::B })
-                .collect()
-        })
-        .collect();
-    (exprs, messages)
+fn count_or_exit(exprs: &HashMap<usize, Expr>, messages: &[&str]) -> usize {
+    count_matches(exprs, messages).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    })
 }
 
 fn main() {
     let (mut exprs, messages) = parse_input(include_str!("../../inputs/day19.txt"));
-    let matched_messages_count = messages
-        .iter()
-        .filter(|msg| {
-            exprs[&0]
-                .matched_len(msg, &exprs)
-                .map_or(false, |len| len == msg.len())
-        })
-        .count();
-    println!("Part 1: {matched_messages_count}");
-
-    exprs.insert(
-        8,
-        Expr::Or(vec![
-            Expr::Ref(42),
-            Expr::Concat(vec![Expr::Ref(42), Expr::Ref(8)]),
-        ]),
-    );
-    exprs.insert(
-        11,
-        Expr::Or(vec![
-            Expr::Concat(vec![Expr::Ref(42), Expr::Ref(31)]),
-            Expr::Concat(vec![Expr::Ref(42), Expr::Ref(11), Expr::Ref(31)]),
-        ]),
-    );
-    let matched_messages_count = messages
-        .iter()
-        .filter(|msg| {
-            exprs[&0]
-                .matched_lens(msg, &exprs)
-                .into_iter()
-                .any(|len| len == msg.len())
-        })
-        .count();
-    println!("Part 2: {matched_messages_count}");
+    // usage: day19 [--overrides file] [--explain message [--json]] [--language rule [--list n]]
+    // explanations are against the rules with the overrides applied, languages without them
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(pos) = args.iter().position(|arg| arg == "--language") {
+        let rule = args
+            .get(pos + 1)
+            .and_then(|arg| arg.parse().ok())
+            .expect("--language should be given a rule number");
+        let grammar = Grammar::new(&exprs).expect("rules should only refer to existing rules");
+        let list = args.iter().position(|arg| arg == "--list").map(|pos| {
+            args.get(pos + 1)
+                .and_then(|arg| arg.parse::<usize>().ok())
+                .expect("--list should be given a count")
+        });
+        let result = grammar.count_strings(rule).and_then(|count| {
+            println!("Rule {} matches {} distinct strings", rule, count);
+            // only spell the strings out when they are asked for
+            for string in grammar.language(rule)?.take(list.unwrap_or_default()) {
+                println!("{}", string);
+            }
+            Ok(())
+        });
+        if let Err(err) = result {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    let explain = args.iter().position(|arg| arg == "--explain");
+    if explain.is_none() {
+        println!("Part 1: {}", count_or_exit(&exprs, &messages));
+    }
+    let overrides = match args.iter().position(|arg| arg == "--overrides") {
+        Some(pos) => {
+            let path = args.get(pos + 1).expect("--overrides should name a file");
+            std::fs::read_to_string(path).expect("overrides should be readable")
+        }
+        None => PART2_OVERRIDES.to_owned(),
+    };
+    let overrides = parse_rules(overrides.lines().filter(|line| !line.trim().is_empty()))
+        .expect("overrides should parse");
+    exprs.extend(overrides);
+    if let Some(pos) = explain {
+        let msg = args
+            .get(pos + 1)
+            .expect("--explain should be given a message");
+        let grammar = Grammar::new(&exprs).expect("rules should only refer to existing rules");
+        let msg = grammar.parse_message(msg).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        });
+        let explanation = grammar.explain(0, &msg);
+        if args.iter().any(|arg| arg == "--json") {
+            println!("{}", explanation.to_json());
+        } else {
+            print!("{}", explanation);
+        }
+        return;
+    }
+    println!("Part 2: {}", count_or_exit(&exprs, &messages));
 }
 
 #[cfg(test)]
@@ -180,22 +888,17 @@ mod tests {
         exprs.insert(
             0,
             Expr::Concat(vec![
-                Expr::Const(Char::A),
-                Expr::Const(Char::A),
-                Expr::Const(Char::A),
+                Expr::Const("a".to_owned()),
+                Expr::Const("a".to_owned()),
+                Expr::Const("a".to_owned()),
             ]),
         );
-        assert_eq!(exprs[&0].matched_lens(&[], &exprs), vec![]);
-        assert_eq!(exprs[&0].matched_lens(&[Char::A], &exprs), vec![]);
-        assert_eq!(exprs[&0].matched_lens(&[Char::A, Char::A], &exprs), vec![]);
-        assert_eq!(
-            exprs[&0].matched_lens(&[Char::A, Char::A, Char::A], &exprs),
-            vec![3]
-        );
-        assert_eq!(
-            exprs[&0].matched_lens(&[Char::A, Char::A, Char::A, Char::A], &exprs),
-            vec![3]
-        );
+        let grammar = Grammar::new(&exprs).unwrap();
+        assert_eq!(grammar.matched_lens(0, &[]), vec![]);
+        assert_eq!(grammar.matched_lens(0, &['a']), vec![]);
+        assert_eq!(grammar.matched_lens(0, &['a', 'a']), vec![]);
+        assert_eq!(grammar.matched_lens(0, &['a', 'a', 'a']), vec![3]);
+        assert_eq!(grammar.matched_lens(0, &['a', 'a', 'a', 'a']), vec![3]);
     }
 
     #[test]
@@ -203,10 +906,316 @@ mod tests {
         let mut exprs = HashMap::new();
         exprs.insert(
             0,
-            Expr::Or(vec![Expr::Const(Char::A), Expr::Const(Char::B)]),
+            Expr::Or(vec![
+                Expr::Const("a".to_owned()),
+                Expr::Const("b".to_owned()),
+            ]),
+        );
+        let grammar = Grammar::new(&exprs).unwrap();
+        assert_eq!(grammar.matched_lens(0, &['a']), vec![1]);
+        assert_eq!(grammar.matched_lens(0, &['b']), vec![1]);
+        assert_eq!(grammar.matched_lens(0, &['a', 'a']), vec![1]);
+    }
+
+    const SAMPLE: &str = "0: 4 1 5
+1: 2 3 | 3 2
+2: 4 4 | 5 5
+3: 4 5 | 5 4
+4: \"a\"
+5: \"b\"
+
+ababbb
+bababa
+abbbab
+aaabbb
+aaaabbb";
+
+    fn msg(s: &str) -> Vec<char> {
+        s.chars().collect()
+    }
+
+    #[test]
+    fn test_sample() {
+        let (exprs, messages) = parse_input(SAMPLE);
+        let grammar = Grammar::new(&exprs).unwrap();
+        let matched: Vec<bool> = messages
+            .iter()
+            .map(|m| grammar.matches(0, &msg(m)))
+            .collect();
+        assert_eq!(matched, vec![true, false, true, false, false]);
+        assert_eq!(count_matches(&exprs, &messages), Ok(2));
+    }
+
+    #[test]
+    fn test_recursion() {
+        let rules = "0: 0 1 | 1\n1: \"a\"\n2: 1 2 3 | 1 3\n3: \"b\"\n4: 0 2";
+        let grammar = Grammar::new(&parse_rules(rules.lines()).unwrap()).unwrap();
+        assert_eq!(grammar.matched_lens(0, &msg("aaab")), vec![1, 2, 3]);
+        assert!(grammar.matches(2, &msg("aaabbb")));
+        assert!(!grammar.matches(2, &msg("aaabb")));
+        assert!(grammar.matches(4, &msg("aaaaabb")));
+        assert!(!grammar.matches(4, &msg("abb")));
+    }
+
+    #[test]
+    fn test_overrides() {
+        let (mut exprs, _) = parse_input(SAMPLE);
+        exprs.extend(parse_rules("1: 1 1 | 2 3 | 3 2".lines()).unwrap());
+        let grammar = Grammar::new(&exprs).unwrap();
+        assert!(grammar.matches(0, &msg("aaaababaab")));
+        assert!(!grammar.matches(0, &msg("aaaababab")));
+
+        exprs.insert(1, Expr::Ref(6));
+        assert_eq!(
+            Grammar::new(&exprs).unwrap_err(),
+            GrammarError::UnknownRule(6)
+        );
+    }
+
+    #[test]
+    fn test_explain_match() {
+        let (exprs, _) = parse_input(SAMPLE);
+        let grammar = Grammar::new(&exprs).unwrap();
+        let explanation = grammar.explain(0, &msg("ababbb"));
+        let lines = [
+            "0",
+            "  4",
+            "    \"a\"",
+            "  1",
+            "    3",
+            "      5",
+            "        \"b\"",
+            "      4",
+            "        \"a\"",
+            "    2",
+            "      5",
+            "        \"b\"",
+            "      5",
+            "        \"b\"",
+            "  5",
+            "    \"b\"",
+        ];
+        assert_eq!(explanation.to_string(), lines.join("\n") + "\n");
+        let leaf = |rule, literal: &str| Tree::Rule {
+            rule,
+            children: vec![Tree::Term(literal.to_owned())],
+        };
+        assert_eq!(
+            grammar.explain(2, &msg("aa")).to_json(),
+            format!(
+                "{{\"match\":true,\"tree\":{}}}",
+                Tree::Rule {
+                    rule: 2,
+                    children: vec![leaf(4, "a"), leaf(4, "a")]
+                }
+                .to_json()
+            )
+        );
+        assert_eq!(
+            grammar.explain(2, &msg("aa")).to_json(),
+            "{\"match\":true,\"tree\":{\"rule\":2,\"children\":[\
+             {\"rule\":4,\"children\":[\"a\"]},{\"rule\":4,\"children\":[\"a\"]}]}}"
+        );
+    }
+
+    #[test]
+    fn test_explain_failure() {
+        let (exprs, _) = parse_input(SAMPLE);
+        let grammar = Grammar::new(&exprs).unwrap();
+        let failure = Failure {
+            matched: 4,
+            found: Some('b'),
+            expected: vec![(4, "a".to_owned())],
+            end_allowed: false,
+        };
+        assert_eq!(
+            grammar.explain(0, &msg("abbbbb")),
+            Explanation::NoMatch(failure.clone())
+        );
+        assert_eq!(
+            failure.to_string(),
+            "no match after 4 char(s), found \"b\", expected \"a\" (rule 4)"
+        );
+        assert_eq!(
+            failure.to_json(),
+            "{\"matched\":4,\"found\":\"b\",\"expected\":[{\"rule\":4,\"term\":\"a\"}],\"end_allowed\":false}"
+        );
+        assert_eq!(
+            grammar.explain(0, &msg("abab")),
+            Explanation::NoMatch(Failure {
+                matched: 4,
+                found: None,
+                expected: vec![(5, "b".to_owned())],
+                end_allowed: false,
+            })
+        );
+        assert_eq!(
+            grammar.explain(0, &msg("abbbbb")).to_string(),
+            "no match after 4 char(s), found \"b\", expected \"a\" (rule 4)\n"
+        );
+        let rules = "0: 1 | 0 1\n1: \"a\"";
+        let grammar = Grammar::new(&parse_rules(rules.lines()).unwrap()).unwrap();
+        assert_eq!(
+            grammar.explain(0, &msg("aab")).to_string(),
+            "no match after 2 char(s), found \"b\", expected \"a\" (rule 1) or end of message\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_literals() {
+        assert_eq!("\"a\"".parse(), Ok(Expr::Const("a".to_owned())));
+        assert_eq!(
+            "1 \"x|y z\" | \"\\\"\\\\\"".parse(),
+            Ok(Expr::Or(vec![
+                Expr::Concat(vec![Expr::Ref(1), Expr::Const("x|y z".to_owned())]),
+                Expr::Const("\"\\".to_owned()),
+            ]))
+        );
+        assert_eq!(
+            "12 3".parse(),
+            Ok(Expr::Concat(vec![Expr::Ref(12), Expr::Ref(3)]))
+        );
+        assert_eq!("\"ab".parse::<Expr>(), Err(()));
+        assert_eq!("1 |".parse::<Expr>(), Err(()));
+        assert_eq!("1 x".parse::<Expr>(), Err(()));
+    }
+
+    #[test]
+    fn test_multi_char_literals() {
+        let rules = "0: 1 2 1\n1: \"αβ\" | \"γ\"\n2: \"--\" | \"\" | 2 \"!\"";
+        let grammar = Grammar::new(&parse_rules(rules.lines()).unwrap()).unwrap();
+        assert!(grammar.matches(0, &msg("αβ--γ")));
+        assert!(grammar.matches(0, &msg("γγ")));
+        assert!(grammar.matches(0, &msg("αβ!!αβ")));
+        assert!(!grammar.matches(0, &msg("αγ")));
+        assert!(!grammar.matches(0, &msg("γ-γ")));
+
+        let explanation = grammar.explain(0, &msg("γ--!γ"));
+        let lines = [
+            "0",
+            "  1",
+            "    \"γ\"",
+            "  2",
+            "    2",
+            "      \"--\"",
+            "    \"!\"",
+            "  1",
+            "    \"γ\"",
+        ];
+        assert_eq!(explanation.to_string(), lines.join("\n") + "\n");
+        assert_eq!(
+            grammar.explain(0, &msg("αβ-γ")),
+            Explanation::NoMatch(Failure {
+                matched: 2,
+                found: Some('-'),
+                expected: vec![
+                    (1, "αβ".to_owned()),
+                    (1, "γ".to_owned()),
+                    (2, "!".to_owned()),
+                    (2, "--".to_owned())
+                ],
+                end_allowed: false,
+            })
+        );
+    }
+
+    #[test]
+    fn test_invalid_messages() {
+        let (exprs, _) = parse_input(SAMPLE);
+        let grammar = Grammar::new(&exprs).unwrap();
+        assert_eq!(grammar.parse_message("ab"), Ok(vec!['a', 'b']));
+        let err = InvalidMessage {
+            message: "abxb".to_owned(),
+            c: 'x',
+            column: 3,
+        };
+        assert_eq!(grammar.parse_message("abxb"), Err(err.clone()));
+        assert_eq!(
+            err.to_string(),
+            "unknown character 'x' at column 3 of \"abxb\""
+        );
+        assert_eq!(count_matches(&exprs, &["ababbb", "abxb"]), Err(err));
+    }
+
+    #[test]
+    fn test_compile() {
+        let (exprs, messages) = parse_input(SAMPLE);
+        let grammar = Grammar::new(&exprs).unwrap();
+        let regex = grammar.to_regex(0).unwrap();
+        assert_eq!(
+            regex.as_str(),
+            "^a(?:(?:aa|bb)(?:ab|ba)|(?:ab|ba)(?:aa|bb))b$"
+        );
+        let matched: Vec<bool> = messages.iter().map(|m| regex.is_match(m)).collect();
+        assert_eq!(matched, vec![true, false, true, false, false]);
+
+        let rules = "0: 1 \"+\" | \"x\"\n1: \"a.\" | 0";
+        let grammar = Grammar::new(&parse_rules(rules.lines()).unwrap()).unwrap();
+        assert!(matches!(
+            grammar.to_regex(0),
+            Err(CompileError::Recursive(0))
+        ));
+        let grammar =
+            Grammar::new(&parse_rules("0: \"a.\" \"+\" | \"x\"".lines()).unwrap()).unwrap();
+        let regex = grammar.to_regex(0).unwrap();
+        assert!(regex.is_match("a.+"));
+        assert!(!regex.is_match("ab+"));
+        assert!(!regex.is_match("a.+x"));
+    }
+
+    #[test]
+    fn test_language() {
+        let (exprs, _) = parse_input(SAMPLE);
+        let grammar = Grammar::new(&exprs).unwrap();
+        let language: Vec<String> = grammar.language(1).unwrap().collect();
+        assert_eq!(language.len(), 8);
+        assert_eq!(language[..3], ["aaab", "aaba", "abaa"]);
+        assert!(language.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(grammar.language(0).unwrap().count(), 8);
+        assert_eq!(grammar.count_strings(1).unwrap(), BigUint::from(8u32));
+        assert_eq!(grammar.count_strings(0).unwrap(), BigUint::from(8u32));
+        // "b" and "ab" have two derivations each, they are only counted once
+        let rules = "0: 1 2 | 2 1 | 3\n1: \"a\" | \"\"\n2: \"b\"\n3: \"a\" 2 | \"\" \"b\" \"\"";
+        let grammar = Grammar::new(&parse_rules(rules.lines()).unwrap()).unwrap();
+        assert_eq!(
+            grammar.language(0).unwrap().collect::<Vec<_>>(),
+            vec!["ab", "b", "ba"]
+        );
+        assert_eq!(grammar.count_strings(0).unwrap(), BigUint::from(3u32));
+        let grammar =
+            Grammar::new(&parse_rules("0: 1 1\n1: \"a\" | \"aa\"".lines()).unwrap()).unwrap();
+        assert_eq!(
+            grammar.language(0).unwrap().collect::<Vec<_>>(),
+            vec!["aa", "aaa", "aaaa"]
+        );
+        assert_eq!(grammar.count_strings(0).unwrap(), BigUint::from(3u32));
+        let grammar =
+            Grammar::new(&parse_rules("0: 1 | \"a\"\n1: \"b\" 0".lines()).unwrap()).unwrap();
+        assert!(matches!(
+            grammar.language(0),
+            Err(CompileError::Recursive(_))
+        ));
+        assert!(matches!(
+            grammar.count_strings(0),
+            Err(CompileError::Recursive(_))
+        ));
+
+        // 2^201 strings, far too many to spell out
+        let rules: Vec<String> = (0..200)
+            .map(|rule| format!("{}: 201 {}", rule, rule + 1))
+            .chain(["200: 201".to_owned(), "201: \"a\" | \"b\"".to_owned()])
+            .collect();
+        let grammar =
+            Grammar::new(&parse_rules(rules.iter().map(String::as_str)).unwrap()).unwrap();
+        assert_eq!(grammar.count_strings(199).unwrap(), BigUint::from(4u32));
+        assert_eq!(
+            grammar.count_strings(0).unwrap(),
+            BigUint::from(2u32).pow(201)
+        );
+        // listing stops after the strings that are taken
+        assert_eq!(
+            grammar.language(0).unwrap().nth(1),
+            Some(format!("{}b", "a".repeat(200)))
         );
-        assert_eq!(exprs[&0].matched_lens(&[Char::A], &exprs), vec![1]);
-        assert_eq!(exprs[&0].matched_lens(&[Char::B], &exprs), vec![1]);
-        assert_eq!(exprs[&0].matched_lens(&[Char::A, Char::A], &exprs), vec![1]);
     }
 }
@@ -1,12 +1,11 @@
 use core::hash::Hash;
 use std::{
     collections::{HashMap, HashSet},
-    iter::repeat,
+    fmt,
+    iter::{repeat, successors},
     str::FromStr,
 };
 
-use num::integer::Roots;
-
 #[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
 struct Id(usize);
 
@@ -27,13 +26,116 @@ enum Edge {
     Right,
 }
 
-trait TileId {
-    fn tile_id(&self) -> usize;
+impl Edge {
+    const ALL: [Self; 4] = [Edge::Top, Edge::Bottom, Edge::Left, Edge::Right];
+}
+
+/// Element of the dihedral group D4 acting on a tile: the columns are mirrored if
+/// `flipped`, then the tile is rotated counter-clockwise `rotations` times.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+struct Orientation {
+    /// counter-clockwise rotations (0-3)
+    rotations: u8,
+    /// is the tile flipped horizontally (mirrored) before rotating
+    flipped: bool,
+}
+
+impl Orientation {
+    const IDENTITY: Self = Self {
+        rotations: 0,
+        flipped: false,
+    };
+
+    /// Rotated once counter-clockwise
+    const ROTATE: Self = Self {
+        rotations: 1,
+        flipped: false,
+    };
+
+    /// Flipped horizontally
+    const FLIP: Self = Self {
+        rotations: 0,
+        flipped: true,
+    };
+
+    /// All 8 orientations, the unflipped ones first.
+    fn all() -> impl Iterator<Item = Self> {
+        [Self::IDENTITY, Self::FLIP].into_iter().flat_map(|base| {
+            successors(Some(base), |orientation| {
+                Some(Self::ROTATE.compose(*orientation))
+            })
+            .take(4)
+        })
+    }
+
+    /// `self` applied after `other`.
+    fn compose(self, other: Self) -> Self {
+        // a flip turns the rotations that came before it the other way
+        let rotations = if self.flipped {
+            self.rotations + 4 - other.rotations
+        } else {
+            self.rotations + other.rotations
+        };
+        Self {
+            rotations: rotations % 4,
+            flipped: self.flipped != other.flipped,
+        }
+    }
+
+    fn inverse(self) -> Self {
+        if self.flipped {
+            self
+        } else {
+            Self {
+                rotations: (4 - self.rotations) % 4,
+                flipped: false,
+            }
+        }
+    }
+
+    /// Dimensions (rows, cols) after orienting something of dimensions `size`.
+    fn size(self, (rows, cols): (usize, usize)) -> (usize, usize) {
+        if self.rotations % 2 == 1 {
+            (cols, rows)
+        } else {
+            (rows, cols)
+        }
+    }
+
+    /// Where the field at `(row, col)` of something of dimensions `size` ends up.
+    fn apply(self, (row, col): (usize, usize), size: (usize, usize)) -> (usize, usize) {
+        let (mut row, mut col) = (row, col);
+        let (mut rows, mut cols) = size;
+        if self.flipped {
+            col = cols - col - 1;
+        }
+        for _ in 0..self.rotations {
+            (row, col) = (cols - col - 1, row);
+            (rows, cols) = (cols, rows);
+        }
+        (row, col)
+    }
+}
+
+impl fmt::Display for Orientation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.flipped, self.rotations) {
+            (false, 0) => write!(f, "as is"),
+            (false, rotations) => {
+                write!(f, "rotated {}° counter-clockwise", rotations as usize * 90)
+            }
+            (true, 0) => write!(f, "flipped"),
+            (true, rotations) => write!(
+                f,
+                "flipped, then rotated {}° counter-clockwise",
+                rotations as usize * 90
+            ),
+        }
+    }
 }
 
-trait EdgeId {
-    fn edge_id(&self, edge: Edge) -> Id;
-    fn rev_edge_id(&self, edge: Edge) -> Id;
+trait TileId {
+    fn tile_id(&self) -> usize;
 }
 
 trait GetTileData {
@@ -42,16 +144,29 @@ trait GetTileData {
     fn get_tile_field(&self, row: usize, col: usize) -> bool;
 }
 
-trait FullTile: TileId + EdgeId + GetTileData {}
+/// Edges read left to right and top to bottom, the first field is the highest bit.
+trait EdgeId: GetTileData {
+    fn edge_fields(&self, edge: Edge) -> Vec<bool> {
+        let (rows, cols) = (self.rows(), self.cols());
+        match edge {
+            Edge::Top => (0..cols).map(|col| self.get_tile_field(0, col)).collect(),
+            Edge::Bottom => (0..cols)
+                .map(|col| self.get_tile_field(rows - 1, col))
+                .collect(),
+            Edge::Left => (0..rows).map(|row| self.get_tile_field(row, 0)).collect(),
+            Edge::Right => (0..rows)
+                .map(|row| self.get_tile_field(row, cols - 1))
+                .collect(),
+        }
+    }
 
-impl<T> FullTile for T where T: TileId + EdgeId + GetTileData {}
+    fn edge_id(&self, edge: Edge) -> Id {
+        self.edge_fields(edge).into_iter().collect()
+    }
 
-trait TileWrapping
-where
-    Self: Sized,
-{
-    fn flip(self) -> FlippedTile<Self>;
-    fn rotate(self) -> RotatedTile<Self>;
+    fn rev_edge_id(&self, edge: Edge) -> Id {
+        self.edge_fields(edge).into_iter().rev().collect()
+    }
 }
 
 #[derive(Clone, Debug, Eq)]
@@ -70,8 +185,15 @@ impl Tile {
         Self { id, data }
     }
 
+    fn oriented(&self, orientation: Orientation) -> TransformedTile<'_> {
+        TransformedTile {
+            tile: self,
+            orientation,
+        }
+    }
+
     fn rotations(&self) -> impl Iterator<Item = TransformedTile<'_>> {
-        TransformedTile::create_all(self).into_iter()
+        Orientation::all().map(|orientation| self.oriented(orientation))
     }
 }
 
@@ -101,9 +223,12 @@ impl FromStr for Tile {
             .ok_or(())?
             .parse::<usize>()
             .map_err(|_| ())?;
-        let data = lines
+        let data: Vec<Vec<bool>> = lines
             .map(|line| line.chars().map(|c| c == '#').collect())
             .collect();
+        if data.is_empty() || data.iter().any(|row| row.len() != data[0].len()) {
+            return Err(());
+        }
         Ok(Self::new(id, data))
     }
 }
@@ -120,57 +245,9 @@ impl TileId for &Tile {
     }
 }
 
-impl EdgeId for Tile {
-    fn edge_id(&self, edge: Edge) -> Id {
-        match edge {
-            Edge::Top => self.data[0].iter().cloned().collect(),
-            Edge::Bottom => self
-                .data
-                .last()
-                .expect("tile can't be empty")
-                .iter()
-                .cloned()
-                .collect(),
-            Edge::Left => self.data.iter().map(|row| row[0]).collect(),
-            Edge::Right => self
-                .data
-                .iter()
-                .map(|row| *row.last().expect("tile rows can't be empty"))
-                .collect(),
-        }
-    }
-
-    fn rev_edge_id(&self, edge: Edge) -> Id {
-        match edge {
-            Edge::Top => self.data[0].iter().rev().cloned().collect(),
-            Edge::Bottom => self
-                .data
-                .last()
-                .expect("tile can't be empty")
-                .iter()
-                .rev()
-                .cloned()
-                .collect(),
-            Edge::Left => self.data.iter().rev().map(|row| row[0]).collect(),
-            Edge::Right => self
-                .data
-                .iter()
-                .rev()
-                .map(|row| *row.last().expect("tile rows can't be empty"))
-                .collect(),
-        }
-    }
-}
-
-impl EdgeId for &Tile {
-    fn edge_id(&self, edge: Edge) -> Id {
-        (*self).edge_id(edge)
-    }
+impl EdgeId for Tile {}
 
-    fn rev_edge_id(&self, edge: Edge) -> Id {
-        (*self).rev_edge_id(edge)
-    }
-}
+impl EdgeId for &Tile {}
 
 impl GetTileData for Tile {
     fn rows(&self) -> usize {
@@ -178,7 +255,7 @@ impl GetTileData for Tile {
     }
 
     fn cols(&self) -> usize {
-        self.data[0].len()
+        self.data.first().map_or(0, Vec::len)
     }
 
     fn get_tile_field(&self, row: usize, col: usize) -> bool {
@@ -200,227 +277,11 @@ impl GetTileData for &Tile {
     }
 }
 
-/// Base tile flipped horizontally
-struct FlippedTile<T> {
-    tile: T,
-}
-
-impl<T> FlippedTile<T> {
-    fn wrap(tile: T) -> Self {
-        Self { tile }
-    }
-}
-
-impl<T> Clone for FlippedTile<T>
-where
-    T: Clone,
-{
-    fn clone(&self) -> Self {
-        Self {
-            tile: self.tile.clone(),
-        }
-    }
-}
-
-impl<T> TileId for FlippedTile<T>
-where
-    T: TileId,
-{
-    fn tile_id(&self) -> usize {
-        self.tile.tile_id()
-    }
-}
-
-impl<T> EdgeId for FlippedTile<T>
-where
-    T: EdgeId,
-{
-    fn edge_id(&self, edge: Edge) -> Id {
-        match edge {
-            Edge::Top | Edge::Bottom => self.tile.rev_edge_id(edge),
-            Edge::Left => self.tile.edge_id(Edge::Right),
-            Edge::Right => self.tile.edge_id(Edge::Left),
-        }
-    }
-
-    fn rev_edge_id(&self, edge: Edge) -> Id {
-        match edge {
-            Edge::Top | Edge::Bottom => self.tile.edge_id(edge),
-            Edge::Left => self.tile.rev_edge_id(Edge::Right),
-            Edge::Right => self.tile.rev_edge_id(Edge::Left),
-        }
-    }
-}
-
-impl<T> GetTileData for FlippedTile<T>
-where
-    T: GetTileData,
-{
-    fn rows(&self) -> usize {
-        self.tile.rows()
-    }
-
-    fn cols(&self) -> usize {
-        self.tile.cols()
-    }
-
-    fn get_tile_field(&self, row: usize, col: usize) -> bool {
-        self.tile.get_tile_field(row, self.cols() - col - 1)
-    }
-}
-
-/// Base tile rotated once counter-clockwise
-struct RotatedTile<T> {
-    tile: T,
-}
-
-impl<T> RotatedTile<T> {
-    fn wrap(tile: T) -> Self {
-        Self { tile }
-    }
-}
-
-impl<T> Clone for RotatedTile<T>
-where
-    T: Clone,
-{
-    fn clone(&self) -> Self {
-        Self {
-            tile: self.tile.clone(),
-        }
-    }
-}
-
-impl<T> TileId for RotatedTile<T>
-where
-    T: TileId,
-{
-    fn tile_id(&self) -> usize {
-        self.tile.tile_id()
-    }
-}
-
-impl<T> EdgeId for RotatedTile<T>
-where
-    T: EdgeId,
-{
-    fn edge_id(&self, edge: Edge) -> Id {
-        match edge {
-            Edge::Top => self.tile.edge_id(Edge::Right),
-            Edge::Right => self.tile.rev_edge_id(Edge::Bottom),
-            Edge::Bottom => self.tile.edge_id(Edge::Left),
-            Edge::Left => self.tile.rev_edge_id(Edge::Top),
-        }
-    }
-
-    fn rev_edge_id(&self, edge: Edge) -> Id {
-        match edge {
-            Edge::Top => self.tile.rev_edge_id(Edge::Right),
-            Edge::Right => self.tile.edge_id(Edge::Bottom),
-            Edge::Bottom => self.tile.rev_edge_id(Edge::Left),
-            Edge::Left => self.tile.edge_id(Edge::Top),
-        }
-    }
-}
-
-impl<T> GetTileData for RotatedTile<T>
-where
-    T: GetTileData,
-{
-    fn rows(&self) -> usize {
-        self.tile.cols()
-    }
-
-    fn cols(&self) -> usize {
-        self.tile.rows()
-    }
-
-    fn get_tile_field(&self, row: usize, col: usize) -> bool {
-        self.tile.get_tile_field(col, self.rows() - row - 1)
-    }
-}
-
-impl<T> TileWrapping for T {
-    fn flip(self) -> FlippedTile<Self> {
-        FlippedTile::wrap(self)
-    }
-
-    fn rotate(self) -> RotatedTile<Self> {
-        RotatedTile::wrap(self)
-    }
-}
-
+/// View of a tile in some orientation.
 #[derive(Clone, Copy, Debug)]
 struct TransformedTile<'a> {
     tile: &'a Tile,
-    /// counter-clockwise rotations (0-3)
-    rotations: u8,
-    /// is the tile flipped horizontally (mirrored)
-    flipped: bool,
-}
-
-impl<'a> TransformedTile<'a> {
-    fn create_all(tile: &'a Tile) -> [Self; 8] {
-        [
-            Self {
-                tile,
-                rotations: 0,
-                flipped: false,
-            },
-            Self {
-                tile,
-                rotations: 1,
-                flipped: false,
-            },
-            Self {
-                tile,
-                rotations: 2,
-                flipped: false,
-            },
-            Self {
-                tile,
-                rotations: 3,
-                flipped: false,
-            },
-            Self {
-                tile,
-                rotations: 0,
-                flipped: true,
-            },
-            Self {
-                tile,
-                rotations: 1,
-                flipped: true,
-            },
-            Self {
-                tile,
-                rotations: 2,
-                flipped: true,
-            },
-            Self {
-                tile,
-                rotations: 3,
-                flipped: true,
-            },
-        ]
-    }
-
-    fn map<F, R>(&self, function: F) -> R
-    where
-        F: Fn(&dyn FullTile) -> R,
-    {
-        match (self.flipped, self.rotations % 4) {
-            (false, 0) => function(&self.tile),
-            (false, 1) => function(&self.tile.rotate()),
-            (false, 2) => function(&self.tile.rotate().rotate()),
-            (false, 3) => function(&self.tile.rotate().rotate().rotate()),
-            (true, 0) => function(&self.tile.flip()),
-            (true, 1) => function(&self.tile.flip().rotate()),
-            (true, 2) => function(&self.tile.flip().rotate().rotate()),
-            (true, 3) => function(&self.tile.flip().rotate().rotate().rotate()),
-            _ => unreachable!(),
-        }
-    }
+    orientation: Orientation,
 }
 
 impl<'a> TileId for TransformedTile<'a> {
@@ -429,27 +290,27 @@ impl<'a> TileId for TransformedTile<'a> {
     }
 }
 
-impl<'a> EdgeId for TransformedTile<'a> {
-    fn edge_id(&self, edge: Edge) -> Id {
-        self.map(|tile| tile.edge_id(edge))
-    }
-
-    fn rev_edge_id(&self, edge: Edge) -> Id {
-        self.map(|tile| tile.rev_edge_id(edge))
-    }
-}
+impl<'a> EdgeId for TransformedTile<'a> {}
 
 impl<'a> GetTileData for TransformedTile<'a> {
     fn rows(&self) -> usize {
-        self.map(|tile| tile.rows())
+        self.orientation
+            .size((self.tile.rows(), self.tile.cols()))
+            .0
     }
 
     fn cols(&self) -> usize {
-        self.map(|tile| tile.cols())
+        self.orientation
+            .size((self.tile.rows(), self.tile.cols()))
+            .1
     }
 
     fn get_tile_field(&self, row: usize, col: usize) -> bool {
-        self.map(|tile| tile.get_tile_field(row, col))
+        let (row, col) = self
+            .orientation
+            .inverse()
+            .apply((row, col), (self.rows(), self.cols()));
+        self.tile.get_tile_field(row, col)
     }
 }
 
@@ -460,13 +321,21 @@ struct Solution<'a> {
 }
 
 impl<'a> Solution<'a> {
-    fn with_size(size: usize) -> Self {
+    fn with_size(rows: usize, cols: usize) -> Self {
         Self {
-            map: vec![vec![None; size]; size],
+            map: vec![vec![None; cols]; rows],
             used_tiles: Default::default(),
         }
     }
 
+    fn rows(&self) -> usize {
+        self.map.len()
+    }
+
+    fn cols(&self) -> usize {
+        self.map[0].len()
+    }
+
     fn put(&mut self, row: usize, col: usize, rotated_tile: TransformedTile<'a>) -> Result<(), ()> {
         assert!(self.map[row][col].is_none());
         if self.used_tiles.contains(rotated_tile.tile) {
@@ -491,15 +360,15 @@ impl<'a> Solution<'a> {
                 .all(|b| b.is_some()),
             "should be solved by now"
         );
-        [
-            self.map.first().unwrap().first(),
-            self.map.first().unwrap().last(),
-            self.map.last().unwrap().first(),
-            self.map.last().unwrap().last(),
-        ]
-        .into_iter()
-        .map(|x| x.unwrap().unwrap().tile_id())
-        .product()
+        // a single row or column has only two corners, a single tile just one
+        let corners: HashSet<(usize, usize)> = [0, self.rows() - 1]
+            .into_iter()
+            .flat_map(|row| [(row, 0), (row, self.cols() - 1)])
+            .collect();
+        corners
+            .into_iter()
+            .map(|(row, col)| self.map[row][col].unwrap().tile_id())
+            .product()
     }
 
     fn merge_image(&self) -> Option<Tile> {
@@ -512,20 +381,22 @@ impl<'a> Solution<'a> {
             return None;
         }
 
-        let map_size = self.map.len();
-
         // dimensions of a tile to merge
         let part_tile_size = self.map[0][0]
             .map(|t| (t.rows(), t.cols()))
             .expect("already checked above");
 
-        // dimensions of a tile after chopping the edges
-        let chopped_tile_size = (part_tile_size.0 - 2, part_tile_size.1 - 2);
+        // dimensions of a tile after chopping the edges, nothing is left of tiles that are
+        // less than 3 fields wide
+        let chopped_tile_size = (
+            part_tile_size.0.saturating_sub(2),
+            part_tile_size.1.saturating_sub(2),
+        );
 
         // dimensions of a merged tile
         let full_tile_size = (
-            chopped_tile_size.0 * map_size,
-            chopped_tile_size.1 * map_size,
+            chopped_tile_size.0 * self.rows(),
+            chopped_tile_size.1 * self.cols(),
         );
 
         // merge all tiles into one (discarding the edges)
@@ -533,8 +404,8 @@ impl<'a> Solution<'a> {
         for (row_id, row) in self.map.iter().enumerate() {
             for (col_id, tile) in row.iter().enumerate() {
                 let tile = tile.expect("already checked above");
-                for tile_row_idx in 1..(tile.rows() - 1) {
-                    for tile_col_idx in 1..(tile.cols() - 1) {
+                for tile_row_idx in 1..tile.rows().saturating_sub(1) {
+                    for tile_col_idx in 1..tile.cols().saturating_sub(1) {
                         data[row_id * chopped_tile_size.0 + tile_row_idx - 1]
                             [col_id * chopped_tile_size.1 + tile_col_idx - 1] =
                             tile.get_tile_field(tile_row_idx, tile_col_idx);
@@ -548,25 +419,93 @@ impl<'a> Solution<'a> {
     }
 }
 
+/// One line per placed tile with its position and orientation.
+impl<'a> fmt::Display for Solution<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (row_id, row) in self.map.iter().enumerate() {
+            for (col_id, tile) in row.iter().enumerate() {
+                match tile {
+                    Some(tile) => writeln!(
+                        f,
+                        "row {}, col {}: tile {} {}",
+                        row_id,
+                        col_id,
+                        tile.tile_id(),
+                        tile.orientation
+                    )?,
+                    None => writeln!(f, "row {}, col {}: empty", row_id, col_id)?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum LayoutError {
+    NoTiles,
+    /// (rows, cols) of a tile that doesn't have the dimensions of the first one, in either
+    /// orientation
+    TileSize {
+        id: usize,
+        size: (usize, usize),
+        expected: (usize, usize),
+    },
+    /// every layout (rows, cols) of the tiles was tried
+    NoArrangement {
+        tried: Vec<(usize, usize)>,
+    },
+}
+
+impl fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LayoutError::NoTiles => write!(f, "there are no tiles to arrange"),
+            LayoutError::TileSize { id, size, expected } => write!(
+                f,
+                "tile {} is {}x{} but the tiles should be {}x{}",
+                id, size.0, size.1, expected.0, expected.1
+            ),
+            LayoutError::NoArrangement { tried } => {
+                let tried: Vec<String> = tried
+                    .iter()
+                    .map(|(rows, cols)| format!("{}x{}", rows, cols))
+                    .collect();
+                write!(
+                    f,
+                    "the tiles can't be arranged, tried layouts {}",
+                    tried.join(", ")
+                )
+            }
+        }
+    }
+}
+
 struct Solver<'a> {
     tiles: &'a [Tile],
-    size: usize,
     top_edge_id_to_tile: HashMap<Id, Vec<TransformedTile<'a>>>,
     left_edge_id_to_tile: HashMap<Id, Vec<TransformedTile<'a>>>,
     top_left_edge_id_to_tile: HashMap<(Id, Id), Vec<TransformedTile<'a>>>,
+    /// tile id to the number of its edges no other tile has
+    unmatched_edges: HashMap<usize, usize>,
 }
 
 impl<'a> Solver<'a> {
-    fn with_tiles(tiles: &'a [Tile]) -> Self {
-        let size = tiles.len().sqrt();
-        assert_eq!(
-            size * size,
-            tiles.len(),
-            "tiles should be able to form a square"
-        );
-        Self {
+    fn with_tiles(tiles: &'a [Tile]) -> Result<Self, LayoutError> {
+        let first = tiles.first().ok_or(LayoutError::NoTiles)?;
+        let expected = (first.rows(), first.cols());
+        for tile in tiles {
+            let size = (tile.rows(), tile.cols());
+            if size != expected && size != (expected.1, expected.0) {
+                return Err(LayoutError::TileSize {
+                    id: tile.id,
+                    size,
+                    expected,
+                });
+            }
+        }
+        Ok(Self {
             tiles,
-            size,
             top_edge_id_to_tile: Self::build_edge_id_to_tile_map(tiles, Edge::Top),
             left_edge_id_to_tile: Self::build_edge_id_to_tile_map(tiles, Edge::Left),
             top_left_edge_id_to_tile: Self::build_double_edge_id_to_tile_map(
@@ -574,7 +513,32 @@ impl<'a> Solver<'a> {
                 Edge::Top,
                 Edge::Left,
             ),
+            unmatched_edges: Self::count_unmatched_edges(tiles),
+        })
+    }
+
+    fn count_unmatched_edges(tiles: &[Tile]) -> HashMap<usize, usize> {
+        // the same edge in either direction, since the neighbour may be flipped
+        let canonical = |tile: &Tile, edge| tile.edge_id(edge).0.min(tile.rev_edge_id(edge).0);
+        let mut edge_to_tiles: HashMap<usize, HashSet<usize>> = HashMap::new();
+        for tile in tiles {
+            for edge in Edge::ALL {
+                edge_to_tiles
+                    .entry(canonical(tile, edge))
+                    .or_default()
+                    .insert(tile.id);
+            }
         }
+        tiles
+            .iter()
+            .map(|tile| {
+                let unmatched = Edge::ALL
+                    .into_iter()
+                    .filter(|edge| edge_to_tiles[&canonical(tile, *edge)].len() == 1)
+                    .count();
+                (tile.id, unmatched)
+            })
+            .collect()
     }
 
     fn build_edge_id_to_tile_map(
@@ -608,8 +572,42 @@ impl<'a> Solver<'a> {
         map
     }
 
-    fn solve(&'a self) -> Option<Solution<'a>> {
-        self.solve_at(0, 0, Solution::with_size(self.size)).ok()
+    /// Layouts (rows, cols) with room for exactly all tiles. The ones that agree with how
+    /// many tiles have unmatched edges come first, but since edges can match by accident
+    /// the others still follow.
+    fn candidate_layouts(&self) -> Vec<(usize, usize)> {
+        let count = self.tiles.len();
+        let border = self.unmatched_edges.values().filter(|n| **n >= 1).count();
+        let corners = self.unmatched_edges.values().filter(|n| **n >= 2).count();
+        let expected = |rows: usize, cols: usize| {
+            if rows == 1 || cols == 1 {
+                // every tile is on the border and at least two of its edges are
+                (count, count)
+            } else {
+                (2 * (rows + cols) - 4, 4)
+            }
+        };
+        // squarer layouts first when the counts can't tell them apart
+        let mut factors: Vec<(usize, usize)> = (1..=count)
+            .filter(|rows| count.is_multiple_of(*rows))
+            .map(|rows| (rows, count / rows))
+            .collect();
+        factors.sort_by_key(|(rows, cols)| (rows.abs_diff(*cols), *rows));
+        let (mut layouts, others): (Vec<_>, Vec<_>) = factors
+            .into_iter()
+            .partition(|(rows, cols)| expected(*rows, *cols) == (border, corners));
+        layouts.extend(others);
+        layouts
+    }
+
+    fn solve(&'a self) -> Result<Solution<'a>, LayoutError> {
+        let layouts = self.candidate_layouts();
+        for (rows, cols) in &layouts {
+            if let Ok(solution) = self.solve_at(0, 0, Solution::with_size(*rows, *cols)) {
+                return Ok(solution);
+            }
+        }
+        Err(LayoutError::NoArrangement { tried: layouts })
     }
 
     fn solve_at(
@@ -618,13 +616,15 @@ impl<'a> Solver<'a> {
         mut col: usize,
         mut solution: Solution<'a>,
     ) -> Result<Solution<'a>, Solution<'a>> {
-        if col >= self.size {
+        if col >= solution.cols() {
             row += 1;
             col = 0;
         }
-        if row == self.size {
+        if row == solution.rows() {
             return Ok(solution);
         }
+        // non-square tiles all have to lie the same way for their edges to line up
+        let tile_size = solution.map[0][0].map(|tile| (tile.rows(), tile.cols()));
 
         let left_edge_id = col.checked_sub(1).map(|left_col| {
             solution.map[row][left_col]
@@ -643,6 +643,9 @@ impl<'a> Solver<'a> {
                 // FIXME: tiles_to_try_temp_storage is only needed so we can return a borrow from this match
                 tiles_to_try_temp_storage
                     .extend(self.tiles.iter().flat_map(|tile| tile.rotations()));
+                // likely corners first, the rest still gets tried if they don't work out
+                tiles_to_try_temp_storage
+                    .sort_by_key(|tile| std::cmp::Reverse(self.unmatched_edges[&tile.tile_id()]));
                 Some(&tiles_to_try_temp_storage)
             }
             (Some(top_edge_id), None) => self.top_edge_id_to_tile.get(&top_edge_id),
@@ -654,6 +657,10 @@ impl<'a> Solver<'a> {
 
         if let Some(rotated_tiles_to_try) = rotated_tiles_to_try {
             for rotated_tile in rotated_tiles_to_try {
+                if tile_size.is_some_and(|size| size != (rotated_tile.rows(), rotated_tile.cols()))
+                {
+                    continue;
+                }
                 if solution.put(row, col, *rotated_tile).is_ok() {
                     match self.solve_at(row, col + 1, solution) {
                         Ok(solution) => return Ok(solution),
@@ -672,9 +679,16 @@ fn find_patterns<T: GetTileData>(tile: T, pattern: &[(usize, usize)]) -> Vec<(us
         pattern.iter().map(|(i, _)| *i).max().unwrap_or_default() + 1,
         pattern.iter().map(|(_, i)| *i).max().unwrap_or_default() + 1,
     );
+    // the pattern doesn't fit at all into a smaller tile
+    let (Some(last_row), Some(last_col)) = (
+        tile.rows().checked_sub(pattern_size.0),
+        tile.cols().checked_sub(pattern_size.1),
+    ) else {
+        return Vec::new();
+    };
     let mut result = Vec::new();
-    for row in 0..(tile.rows() - pattern_size.0) {
-        for col in 0..(tile.cols() - pattern_size.1) {
+    for row in 0..=last_row {
+        for col in 0..=last_col {
             if pattern.iter().all(|(pattern_row, pattern_col)| {
                 tile.get_tile_field(row + *pattern_row, col + *pattern_col)
             }) {
@@ -691,8 +705,16 @@ fn main() {
         .map(|s| s.parse::<Tile>())
         .collect::<Result<Vec<_>, _>>()
         .expect("input should parse correctly");
-    let solver = Solver::with_tiles(&tiles);
-    let solution = solver.solve().expect("there should be a solution");
+    let exit = |err: LayoutError| -> ! {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    };
+    let solver = Solver::with_tiles(&tiles).unwrap_or_else(|err| exit(err));
+    let solution = solver.solve().unwrap_or_else(|err| exit(err));
+    // usage: day20 [--layout]
+    if std::env::args().skip(1).any(|arg| arg == "--layout") {
+        print!("{}", solution);
+    }
     println!("Part 1: {}", solution.checksum());
 
     let pattern: Vec<(usize, usize)> = concat!(
@@ -727,7 +749,11 @@ fn main() {
             points.remove(&(offset_row + *row, offset_col + *col));
         }
     }
-    println!("Part 2: {}", points.len());
+    println!(
+        "Part 2: {} (sea monsters found with the image {})",
+        points.len(),
+        transformed.orientation
+    );
 }
 
 #[cfg(test)]
@@ -800,7 +826,8 @@ mod tests {
          *  ..#...#.#.
          *  ##.###....
          */
-        let tile = get_tile_2383().flip();
+        let tile = get_tile_2383();
+        let tile = tile.oriented(Orientation::FLIP);
 
         assert_eq!(tile.edge_id(Edge::Top), Id(0b1010101000));
         assert_eq!(tile.rev_edge_id(Edge::Top), Id(0b1010101));
@@ -828,7 +855,8 @@ mod tests {
          *  ..#...#.#.
          *  ##.###....
          */
-        let tile = get_tile_2383().rotate();
+        let tile = get_tile_2383();
+        let tile = tile.oriented(Orientation::ROTATE);
 
         assert_eq!(tile.edge_id(Edge::Top), Id(0b1101101000));
         assert_eq!(tile.rev_edge_id(Edge::Top), Id(0b1011011));
@@ -856,7 +884,11 @@ mod tests {
          *  ..#...#.#.
          *  ##.###....
          */
-        let tile = get_tile_2383().flip().rotate().rotate();
+        let tile = get_tile_2383();
+        let tile = tile.oriented(Orientation {
+            rotations: 2,
+            flipped: true,
+        });
 
         assert_eq!(tile.edge_id(Edge::Left), Id(0b1000100000));
         assert_eq!(tile.rev_edge_id(Edge::Left), Id(0b10001));
@@ -887,25 +919,106 @@ mod tests {
     #[test]
     fn test_flip_is_symmetric() {
         let tile = get_tile_2383();
-        let flipped_twice = (&tile).flip().flip();
-        has_same_edges(&tile, flipped_twice.clone());
+        let flip = Orientation::FLIP;
+        assert_eq!(flip.compose(flip), Orientation::IDENTITY);
+        let flipped_twice = tile.oriented(flip.compose(flip));
+        has_same_edges(&tile, flipped_twice);
         has_same_data(&tile, flipped_twice);
     }
 
     #[test]
     fn test_rotate_is_periodic() {
         let tile = get_tile_2383();
-        let rotated_4times = (&tile).rotate().rotate().rotate().rotate();
-        has_same_edges(&tile, rotated_4times.clone());
+        let rotate = Orientation::ROTATE;
+        let rotate_4times = rotate.compose(rotate).compose(rotate).compose(rotate);
+        assert_eq!(rotate_4times, Orientation::IDENTITY);
+        let rotated_4times = tile.oriented(rotate_4times);
+        has_same_edges(&tile, rotated_4times);
         has_same_data(&tile, rotated_4times);
     }
 
     #[test]
     fn test_flip_rotate_combination() {
         let tile = get_tile_2383();
+        let (flip, rotate) = (Orientation::FLIP, Orientation::ROTATE);
         has_same_edges(
-            (&tile).flip().rotate().rotate(),
-            (&tile).rotate().rotate().flip(),
+            tile.oriented(rotate.compose(rotate).compose(flip)),
+            tile.oriented(flip.compose(rotate).compose(rotate)),
+        );
+        // flipping turns rotations the other way
+        assert_eq!(
+            rotate.compose(flip),
+            flip.compose(rotate.compose(rotate).compose(rotate))
+        );
+    }
+
+    #[test]
+    fn test_orientation_group() {
+        let all: Vec<Orientation> = Orientation::all().collect();
+        assert_eq!(all.iter().collect::<HashSet<_>>().len(), 8);
+        for a in &all {
+            assert_eq!(a.compose(a.inverse()), Orientation::IDENTITY);
+            assert_eq!(a.inverse().compose(*a), Orientation::IDENTITY);
+            for b in &all {
+                for c in &all {
+                    assert_eq!(a.compose(*b).compose(*c), a.compose(b.compose(*c)));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_orientation_apply() {
+        let tile = get_tile_2383();
+        // a non-square tile so swapped dimensions show up
+        let wide = Tile::new(1, vec![vec![true, false, false], vec![false, false, true]]);
+        let size = (wide.rows(), wide.cols());
+        assert_eq!(Orientation::ROTATE.apply((0, 0), size), (2, 0));
+        assert_eq!(Orientation::ROTATE.size(size), (3, 2));
+        assert_eq!(Orientation::FLIP.apply((0, 0), size), (0, 2));
+        for a in Orientation::all() {
+            for b in Orientation::all() {
+                for row in 0..size.0 {
+                    for col in 0..size.1 {
+                        assert_eq!(
+                            a.compose(b).apply((row, col), size),
+                            a.apply(b.apply((row, col), size), b.size(size))
+                        );
+                    }
+                }
+            }
+            for t in [&tile, &wide] {
+                let oriented = t.oriented(a);
+                let size = (t.rows(), t.cols());
+                assert_eq!((oriented.rows(), oriented.cols()), a.size(size));
+                for row in 0..size.0 {
+                    for col in 0..size.1 {
+                        let (to_row, to_col) = a.apply((row, col), size);
+                        assert_eq!(
+                            oriented.get_tile_field(to_row, to_col),
+                            t.get_tile_field(row, col)
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_orientation_display() {
+        assert_eq!(Orientation::IDENTITY.to_string(), "as is");
+        assert_eq!(
+            Orientation::ROTATE.to_string(),
+            "rotated 90° counter-clockwise"
+        );
+        assert_eq!(Orientation::FLIP.to_string(), "flipped");
+        assert_eq!(
+            Orientation {
+                rotations: 3,
+                flipped: true
+            }
+            .to_string(),
+            "flipped, then rotated 270° counter-clockwise"
         );
     }
 
@@ -919,11 +1032,7 @@ mod tests {
         flipped: bool,
         rotations: u8,
     ) -> Option<TransformedTile<'_>> {
-        get_tile(tiles, id).map(|tile| TransformedTile {
-            tile,
-            rotations,
-            flipped,
-        })
+        get_tile(tiles, id).map(|tile| tile.oriented(Orientation { rotations, flipped }))
     }
 
     #[test]
@@ -934,7 +1043,7 @@ mod tests {
             .collect::<Result<Vec<_>, _>>()
             .expect("input should parse correctly");
         assert_eq!(tiles.len(), 9);
-        let solver = Solver::with_tiles(&tiles);
+        let solver = Solver::with_tiles(&tiles).unwrap();
 
         let solved = [[
             get_transformed(&tiles, 1951, true, 2).unwrap(),
@@ -969,7 +1078,7 @@ mod tests {
             .expect("input should parse correctly");
         assert_eq!(tiles.len(), 9);
 
-        let mut solution = Solution::with_size(3);
+        let mut solution = Solution::with_size(3, 3);
         solution
             .put(0, 0, get_transformed(&tiles, 1951, true, 2).unwrap())
             .unwrap();
@@ -998,6 +1107,16 @@ mod tests {
             .put(2, 2, get_transformed(&tiles, 1171, true, 0).unwrap())
             .unwrap();
         assert_eq!(solution.checksum(), 20899048083289);
+        let layout = solution.to_string();
+        assert_eq!(layout.lines().count(), 9);
+        assert_eq!(
+            layout.lines().next(),
+            Some("row 0, col 0: tile 1951 flipped, then rotated 180° counter-clockwise")
+        );
+        assert_eq!(
+            layout.lines().last(),
+            Some("row 2, col 2: tile 1171 flipped")
+        );
     }
 
     #[test]
@@ -1024,8 +1143,166 @@ mod tests {
         .collect::<Result<Vec<_>, _>>()
         .expect("input should parse correctly");
         assert_eq!(tiles.len(), 4);
-        let solver = Solver::with_tiles(&tiles);
+        let solver = Solver::with_tiles(&tiles).unwrap();
         let solution = solver.solve().expect("there should be a solution");
         assert_eq!(solution.checksum(), 24);
+        let merged = solution.merge_image().unwrap();
+        assert_eq!((merged.rows(), merged.cols()), (0, 0));
+        assert_eq!(find_patterns(&merged, &[(0, 0)]), vec![]);
+    }
+
+    #[test]
+    fn test_find_patterns_bounds() {
+        let pattern = [(0, 0), (1, 1), (2, 19)];
+        // smaller than the pattern in one dimension
+        let tile = Tile::new(1, vec![vec![true; 4]; 2]);
+        assert_eq!(find_patterns(&tile, &pattern), vec![]);
+        let tile = Tile::new(1, vec![vec![true; 19]; 3]);
+        assert_eq!(find_patterns(&tile, &pattern), vec![]);
+        // exactly the size of the pattern, and touching the last row and column
+        let tile = Tile::new(1, vec![vec![true; 20]; 3]);
+        assert_eq!(find_patterns(&tile, &pattern), vec![(0, 0)]);
+        let tile = Tile::new(1, vec![vec![true; 21]; 4]);
+        assert_eq!(
+            find_patterns(&tile, &pattern),
+            vec![(0, 0), (0, 1), (1, 0), (1, 1)]
+        );
+    }
+
+    /// Tiny deterministic random number generator (a 64-bit LCG), returns 31 bits per call.
+    fn lcg(state: &mut u64) -> u64 {
+        *state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        *state >> 33
+    }
+
+    /// Cuts a random image into `rows` x `cols` tiles of `tile_size` which share their
+    /// edges, numbered row by row from 1, shuffled and each in a random orientation. Also
+    /// returns the image without the shared edges.
+    fn cut_image(
+        rows: usize,
+        cols: usize,
+        tile_size: (usize, usize),
+        seed: u64,
+    ) -> (Vec<Tile>, Vec<Vec<bool>>) {
+        let mut state = seed;
+        let (step_rows, step_cols) = (tile_size.0 - 1, tile_size.1 - 1);
+        let image: Vec<Vec<bool>> = (0..rows * step_rows + 1)
+            .map(|_| {
+                (0..cols * step_cols + 1)
+                    .map(|_| lcg(&mut state) & 1 == 0)
+                    .collect()
+            })
+            .collect();
+        let mut tiles = Vec::new();
+        for row in 0..rows {
+            for col in 0..cols {
+                let data = image[row * step_rows..][..tile_size.0]
+                    .iter()
+                    .map(|line| line[col * step_cols..][..tile_size.1].to_vec())
+                    .collect();
+                let tile = Tile::new(row * cols + col + 1, data);
+                let orientation = Orientation::all()
+                    .nth(lcg(&mut state) as usize % 8)
+                    .unwrap();
+                let view = tile.oriented(orientation);
+                let data = (0..view.rows())
+                    .map(|r| {
+                        (0..view.cols())
+                            .map(|c| view.get_tile_field(r, c))
+                            .collect()
+                    })
+                    .collect();
+                tiles.push(Tile::new(tile.id, data));
+            }
+        }
+        for idx in (1..tiles.len()).rev() {
+            tiles.swap(idx, lcg(&mut state) as usize % (idx + 1));
+        }
+        let inner = image
+            .iter()
+            .enumerate()
+            .filter(|(row, _)| row % step_rows != 0)
+            .map(|(_, line)| {
+                line.iter()
+                    .enumerate()
+                    .filter(|(col, _)| col % step_cols != 0)
+                    .map(|(_, field)| *field)
+                    .collect()
+            })
+            .collect();
+        (tiles, inner)
+    }
+
+    #[test]
+    fn test_rectangular_layouts() {
+        for (rows, cols, tile_size, seed) in [
+            (2, 3, (8, 11), 1),
+            (3, 3, (9, 12), 2),
+            (1, 4, (10, 10), 3),
+            (5, 2, (9, 9), 4),
+        ] {
+            let (tiles, image) = cut_image(rows, cols, tile_size, seed);
+            let solver = Solver::with_tiles(&tiles).unwrap();
+            let layouts = solver.candidate_layouts();
+            assert!(layouts.contains(&(rows, cols)), "{:?}", layouts);
+            assert!(layouts.contains(&(cols, rows)), "{:?}", layouts);
+
+            let solution = solver.solve().unwrap();
+            let corners: HashSet<usize> = [1, cols, (rows - 1) * cols + 1, rows * cols]
+                .into_iter()
+                .collect();
+            assert_eq!(solution.checksum(), corners.into_iter().product());
+            let merged = solution.merge_image().unwrap();
+            assert!(merged.rotations().any(|view| {
+                (view.rows(), view.cols()) == (image.len(), image[0].len())
+                    && image.iter().enumerate().all(|(row, line)| {
+                        line.iter()
+                            .enumerate()
+                            .all(|(col, field)| view.get_tile_field(row, col) == *field)
+                    })
+            }));
+        }
+    }
+
+    #[test]
+    fn test_layout_errors() {
+        assert_eq!(Solver::with_tiles(&[]).err(), Some(LayoutError::NoTiles));
+
+        let tiles = [
+            Tile::new(1, vec![vec![true; 4]; 3]),
+            Tile::new(2, vec![vec![true; 3]; 4]),
+            Tile::new(3, vec![vec![true; 3]; 3]),
+        ];
+        let err = Solver::with_tiles(&tiles).err().unwrap();
+        assert_eq!(
+            err,
+            LayoutError::TileSize {
+                id: 3,
+                size: (3, 3),
+                expected: (3, 4)
+            }
+        );
+        assert_eq!(err.to_string(), "tile 3 is 3x3 but the tiles should be 3x4");
+
+        let tiles = [
+            Tile::new(1, vec![vec![true; 3]; 3]),
+            Tile::new(2, vec![vec![false; 3]; 3]),
+        ];
+        let solver = Solver::with_tiles(&tiles).unwrap();
+        let err = solver.solve().err().unwrap();
+        assert_eq!(
+            err,
+            LayoutError::NoArrangement {
+                tried: vec![(1, 2), (2, 1)]
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "the tiles can't be arranged, tried layouts 1x2, 2x1"
+        );
+
+        assert_eq!("Tile 1:\n##\n#".parse::<Tile>(), Err(()));
     }
 }
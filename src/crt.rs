@@ -0,0 +1,158 @@
+//! Chinese remainder theorem for arbitrary (not necessarily coprime) moduli.
+
+use std::fmt;
+
+use num::{CheckedAdd, CheckedMul, Integer, Signed};
+
+/// All numbers `x` with `x ≡ residue (mod modulus)`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Congruence<T> {
+    pub residue: T,
+    pub modulus: T,
+}
+
+/// The combined modulus (or an intermediate value) doesn't fit into the integer type.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Overflow;
+
+impl fmt::Display for Overflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the combined modulus is too large for the integer type")
+    }
+}
+
+impl<T> Congruence<T>
+where
+    T: Integer + Signed + Clone + CheckedAdd + CheckedMul,
+{
+    /// Creates `x ≡ residue (mod modulus)`, the residue can be any integer.
+    pub fn new(residue: T, modulus: T) -> Self {
+        assert!(modulus.is_positive(), "modulus should be positive");
+        Self {
+            residue: residue.mod_floor(&modulus),
+            modulus,
+        }
+    }
+
+    /// Congruence satisfied by every integer.
+    pub fn any() -> Self {
+        Self {
+            residue: T::zero(),
+            modulus: T::one(),
+        }
+    }
+
+    /// Combines two congruences into one, `Ok(None)` if they have no common solution.
+    pub fn merge(&self, other: &Self) -> Result<Option<Self>, Overflow> {
+        let gcd = self.modulus.extended_gcd(&other.modulus);
+        let diff = other.residue.clone() - self.residue.clone();
+        if !diff.is_multiple_of(&gcd.gcd) {
+            return Ok(None);
+        }
+        let other_reduced = other.modulus.clone() / gcd.gcd.clone();
+        let modulus = self.modulus.checked_mul(&other_reduced).ok_or(Overflow)?;
+        // self.modulus * gcd.x ≡ gcd.gcd (mod other.modulus)
+        let steps = (diff / gcd.gcd)
+            .mod_floor(&other_reduced)
+            .checked_mul(&gcd.x.mod_floor(&other_reduced))
+            .ok_or(Overflow)?
+            .mod_floor(&other_reduced);
+        let residue = self
+            .modulus
+            .checked_mul(&steps)
+            .and_then(|offset| self.residue.checked_add(&offset))
+            .ok_or(Overflow)?
+            .mod_floor(&modulus);
+        Ok(Some(Self { residue, modulus }))
+    }
+
+    /// Smallest solution that is at least `from`.
+    pub fn first_at_least(&self, from: &T) -> T {
+        let behind = (self.residue.clone() - from.clone()).mod_floor(&self.modulus);
+        from.clone() + behind
+    }
+
+    pub fn contains(&self, x: &T) -> bool {
+        x.mod_floor(&self.modulus) == self.residue
+    }
+}
+
+/// Solves a system of congruences, `Ok(None)` if it is inconsistent.
+pub fn solve<T, I>(congruences: I) -> Result<Option<Congruence<T>>, Overflow>
+where
+    T: Integer + Signed + Clone + CheckedAdd + CheckedMul,
+    I: IntoIterator<Item = Congruence<T>>,
+{
+    let mut acc = Congruence::any();
+    for congruence in congruences {
+        match acc.merge(&congruence)? {
+            Some(merged) => acc = merged,
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(acc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use num::BigInt;
+
+    #[test]
+    fn test_coprime() {
+        let solution = solve(vec![
+            Congruence::new(2i128, 3),
+            Congruence::new(3, 5),
+            Congruence::new(2, 7),
+        ]);
+        assert_eq!(solution, Ok(Some(Congruence::new(23, 105))));
+    }
+
+    #[test]
+    fn test_non_coprime() {
+        let solution = solve(vec![Congruence::new(2i128, 4), Congruence::new(4, 6)]);
+        assert_eq!(solution, Ok(Some(Congruence::new(10, 12))));
+        assert_eq!(
+            solve(vec![Congruence::new(1i128, 4), Congruence::new(2, 6)]),
+            Ok(None)
+        );
+        assert_eq!(
+            solve(vec![Congruence::new(3i128, 6), Congruence::new(3, 6)]),
+            Ok(Some(Congruence::new(3, 6)))
+        );
+    }
+
+    #[test]
+    fn test_negative_residues() {
+        let congruence = Congruence::new(-1i128, 5);
+        assert_eq!(congruence.residue, 4);
+        assert!(congruence.contains(&-6));
+        assert_eq!(congruence.first_at_least(&-6), -6);
+        assert_eq!(congruence.first_at_least(&10), 14);
+        assert_eq!(congruence.first_at_least(&14), 14);
+        assert_eq!(
+            solve(Vec::<Congruence<i128>>::new()),
+            Ok(Some(Congruence::any()))
+        );
+    }
+
+    #[test]
+    fn test_overflow() {
+        let big = i128::MAX / 3;
+        let congruences = vec![Congruence::new(1i128, big), Congruence::new(0, big - 1)];
+        assert_eq!(solve(congruences), Err(Overflow));
+        assert_eq!(
+            Overflow.to_string(),
+            "the combined modulus is too large for the integer type"
+        );
+
+        let congruences = vec![
+            Congruence::new(BigInt::from(1), BigInt::from(big)),
+            Congruence::new(BigInt::from(0), BigInt::from(big - 1)),
+        ];
+        let solution = solve(congruences).unwrap().unwrap();
+        assert_eq!(solution.modulus, BigInt::from(big) * BigInt::from(big - 1));
+        assert!(solution.residue.is_multiple_of(&BigInt::from(big - 1)));
+        assert!((solution.residue - BigInt::from(1)).is_multiple_of(&BigInt::from(big)));
+    }
+}
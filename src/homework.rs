@@ -0,0 +1,881 @@
+//! Evaluator for the day 18 homework with configurable operator precedence.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+
+use logos::Logos;
+use num::{BigInt, Signed, ToPrimitive, Zero};
+
+#[derive(Logos, Clone, Debug, PartialEq, Eq)]
+pub enum Token {
+    #[token("+")]
+    Add,
+
+    #[token("-")]
+    Sub,
+
+    #[token("*")]
+    Mul,
+
+    #[token("/")]
+    Div,
+
+    #[token("%")]
+    Mod,
+
+    #[token("^")]
+    Pow,
+
+    #[token("(")]
+    Open,
+
+    #[token(")")]
+    Close,
+
+    /// digits as written, each `Arith` decides what fits
+    #[regex("[0-9]+", |lex| lex.slice().to_owned())]
+    Num(String),
+
+    /// earlier result in a session, `$1` is the first
+    #[regex(r"\$[0-9]+", |lex| lex.slice()[1..].parse())]
+    Ref(usize),
+
+    #[error]
+    #[regex(r"[ \t\n\f]+", logos::skip)]
+    Error,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Open => write!(f, "("),
+            Token::Close => write!(f, ")"),
+            Token::Num(n) => write!(f, "{}", n),
+            Token::Ref(n) => write!(f, "${}", n),
+            Token::Error => write!(f, "?"),
+            token => match BinOp::from_token(token) {
+                Some(op) => write!(f, "{}", op),
+                None => unreachable!(),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+}
+
+impl BinOp {
+    pub fn from_token(token: &Token) -> Option<Self> {
+        match token {
+            Token::Add => Some(BinOp::Add),
+            Token::Sub => Some(BinOp::Sub),
+            Token::Mul => Some(BinOp::Mul),
+            Token::Div => Some(BinOp::Div),
+            Token::Mod => Some(BinOp::Mod),
+            Token::Pow => Some(BinOp::Pow),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for BinOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+            BinOp::Mod => "%",
+            BinOp::Pow => "^",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+impl FromStr for BinOp {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lexer = Token::lexer(s);
+        match (
+            lexer.next().as_ref().and_then(BinOp::from_token),
+            lexer.next(),
+        ) {
+            (Some(op), None) => Ok(op),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Assoc {
+    Left,
+    Right,
+}
+
+/// Binding power and associativity of every operator, higher binds tighter.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Precedence {
+    table: HashMap<BinOp, (u8, Assoc)>,
+    /// binary operators with at least this level end up inside a unary minus, one above
+    /// the highest level by default so it's wider than the levels
+    unary: u16,
+}
+
+impl Precedence {
+    /// Unary minus binds tighter than all the given operators.
+    pub fn new(entries: &[(BinOp, u8, Assoc)]) -> Self {
+        Self {
+            table: entries
+                .iter()
+                .map(|(op, level, assoc)| (*op, (*level, *assoc)))
+                .collect(),
+            unary: entries
+                .iter()
+                .map(|(_, level, _)| u16::from(*level) + 1)
+                .max()
+                .unwrap_or(0),
+        }
+    }
+
+    pub fn with_unary(self, unary: u8) -> Self {
+        Self {
+            unary: unary.into(),
+            ..self
+        }
+    }
+
+    /// Part 1: evaluated left to right.
+    pub fn same() -> Self {
+        use BinOp::*;
+        Self::new(&[Add, Sub, Mul, Div, Mod, Pow].map(|op| (op, 1, Assoc::Left)))
+    }
+
+    /// Part 2: addition (and subtraction) binds tighter.
+    pub fn add_before_mul() -> Self {
+        Self::new(&[
+            (BinOp::Add, 2, Assoc::Left),
+            (BinOp::Sub, 2, Assoc::Left),
+            (BinOp::Mul, 1, Assoc::Left),
+            (BinOp::Div, 1, Assoc::Left),
+            (BinOp::Mod, 1, Assoc::Left),
+            (BinOp::Pow, 3, Assoc::Right),
+        ])
+    }
+
+    /// Ordinary school math, `-2^2` is `-(2^2)`.
+    pub fn math() -> Self {
+        Self::new(&[
+            (BinOp::Add, 1, Assoc::Left),
+            (BinOp::Sub, 1, Assoc::Left),
+            (BinOp::Mul, 2, Assoc::Left),
+            (BinOp::Div, 2, Assoc::Left),
+            (BinOp::Mod, 2, Assoc::Left),
+            (BinOp::Pow, 4, Assoc::Right),
+        ])
+        .with_unary(3)
+    }
+
+    pub fn get(&self, op: BinOp) -> Option<(u8, Assoc)> {
+        self.table.get(&op).copied()
+    }
+}
+
+/// Either a preset name (`same`, `add-first`, `math`) or a table like
+/// `+ 2 left, * 1 right, neg 3` where `neg` sets the level of unary minus.
+impl FromStr for Precedence {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "same" => return Ok(Self::same()),
+            "add-first" => return Ok(Self::add_before_mul()),
+            "math" => return Ok(Self::math()),
+            _ => {}
+        }
+        let mut unary = None;
+        let mut entries = Vec::new();
+        for entry in s.split(',') {
+            let mut parts = entry.split_whitespace();
+            let name = parts.next().ok_or(())?;
+            let level = parts.next().ok_or(())?.parse().map_err(|_| ())?;
+            if name == "neg" {
+                unary = Some(level);
+            } else {
+                let assoc = match parts.next() {
+                    None | Some("left") => Assoc::Left,
+                    Some("right") => Assoc::Right,
+                    _ => return Err(()),
+                };
+                entries.push((name.parse()?, level, assoc));
+            }
+            if parts.next().is_some() {
+                return Err(());
+            }
+        }
+        let precedence = Self::new(&entries);
+        Ok(match unary {
+            Some(unary) => precedence.with_unary(unary),
+            None => precedence,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expr {
+    Num(String),
+    Ref(usize),
+    Neg(Box<Expr>),
+    BinOp(Box<Expr>, BinOp, Box<Expr>),
+}
+
+impl Expr {
+    pub fn eval<T: Arith>(&self) -> Result<T, EvalError> {
+        self.eval_with(&[])
+    }
+
+    /// Evaluates with `$n` referring to `results[n - 1]`.
+    pub fn eval_with<T: Arith>(&self, results: &[T]) -> Result<T, EvalError> {
+        match self {
+            Expr::Num(digits) => T::from_digits(digits),
+            Expr::Ref(n) => n
+                .checked_sub(1)
+                .and_then(|idx| results.get(idx))
+                .cloned()
+                .ok_or(EvalError::UnknownRef(*n)),
+            Expr::Neg(expr) => expr.eval_with(results)?.neg(),
+            Expr::BinOp(lhs, op, rhs) => {
+                T::apply(*op, lhs.eval_with(results)?, rhs.eval_with(results)?)
+            }
+        }
+    }
+}
+
+/// Fully parenthesized, so the grouping the precedence produced is visible.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Num(n) => write!(f, "{}", n),
+            Expr::Ref(n) => write!(f, "${}", n),
+            Expr::Neg(expr) => write!(f, "(-{})", expr),
+            Expr::BinOp(lhs, op, rhs) => write!(f, "({} {} {})", lhs, op, rhs),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EvalError {
+    DivisionByZero,
+    Overflow,
+    NegativeExponent,
+    UnknownRef(usize),
+    LiteralTooLarge,
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::DivisionByZero => write!(f, "division by zero"),
+            EvalError::Overflow => write!(f, "arithmetic overflow"),
+            EvalError::NegativeExponent => write!(f, "negative exponent"),
+            EvalError::UnknownRef(n) => write!(f, "there is no result ${}", n),
+            EvalError::LiteralTooLarge => write!(f, "literal too large"),
+        }
+    }
+}
+
+/// Numbers the evaluator can work with, every operation is checked.
+pub trait Arith: Sized + Clone {
+    /// Value of a literal, `digits` is a non-empty string of ASCII digits.
+    fn from_digits(digits: &str) -> Result<Self, EvalError>;
+    fn neg(self) -> Result<Self, EvalError>;
+    fn apply(op: BinOp, lhs: Self, rhs: Self) -> Result<Self, EvalError>;
+}
+
+impl Arith for i64 {
+    fn from_digits(digits: &str) -> Result<Self, EvalError> {
+        digits.parse().map_err(|_| EvalError::LiteralTooLarge)
+    }
+
+    fn neg(self) -> Result<Self, EvalError> {
+        self.checked_neg().ok_or(EvalError::Overflow)
+    }
+
+    fn apply(op: BinOp, lhs: Self, rhs: Self) -> Result<Self, EvalError> {
+        if matches!(op, BinOp::Div | BinOp::Mod) && rhs == 0 {
+            return Err(EvalError::DivisionByZero);
+        }
+        let result = match op {
+            BinOp::Add => lhs.checked_add(rhs),
+            BinOp::Sub => lhs.checked_sub(rhs),
+            BinOp::Mul => lhs.checked_mul(rhs),
+            BinOp::Div => lhs.checked_div(rhs),
+            BinOp::Mod => lhs.checked_rem(rhs),
+            BinOp::Pow => {
+                if rhs < 0 {
+                    return Err(EvalError::NegativeExponent);
+                }
+                u32::try_from(rhs).ok().and_then(|exp| lhs.checked_pow(exp))
+            }
+        };
+        result.ok_or(EvalError::Overflow)
+    }
+}
+
+impl Arith for BigInt {
+    fn from_digits(digits: &str) -> Result<Self, EvalError> {
+        digits.parse().map_err(|_| EvalError::LiteralTooLarge)
+    }
+
+    fn neg(self) -> Result<Self, EvalError> {
+        Ok(-self)
+    }
+
+    fn apply(op: BinOp, lhs: Self, rhs: Self) -> Result<Self, EvalError> {
+        if matches!(op, BinOp::Div | BinOp::Mod) && rhs.is_zero() {
+            return Err(EvalError::DivisionByZero);
+        }
+        Ok(match op {
+            BinOp::Add => lhs + rhs,
+            BinOp::Sub => lhs - rhs,
+            BinOp::Mul => lhs * rhs,
+            BinOp::Div => lhs / rhs,
+            BinOp::Mod => lhs % rhs,
+            BinOp::Pow => {
+                if rhs.is_negative() {
+                    return Err(EvalError::NegativeExponent);
+                }
+                // even big ints have to stop somewhere
+                lhs.pow(rhs.to_u32().ok_or(EvalError::Overflow)?)
+            }
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    InvalidChar(char),
+    UnexpectedToken(Token),
+    UnexpectedEnd,
+    UnclosedParen {
+        /// where the `(` is, 1-based and in chars
+        column: usize,
+    },
+    UnmatchedParen,
+    /// operator missing from the precedence table
+    UnknownOperator(BinOp),
+}
+
+/// What went wrong and the byte span of the line it points at.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub span: Range<usize>,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ParseErrorKind::InvalidChar(c) => write!(f, "unexpected character {:?}", c),
+            ParseErrorKind::UnexpectedToken(token) => write!(f, "unexpected '{}'", token),
+            ParseErrorKind::UnexpectedEnd => write!(f, "unexpected end of expression"),
+            ParseErrorKind::UnclosedParen { column } => {
+                write!(f, "unclosed '(' opened at column {}", column)
+            }
+            ParseErrorKind::UnmatchedParen => write!(f, "unmatched ')'"),
+            ParseErrorKind::UnknownOperator(op) => {
+                write!(f, "operator '{}' has no precedence", op)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    Parse(ParseError),
+    Eval(EvalError),
+}
+
+impl Error {
+    pub fn span(&self) -> Option<Range<usize>> {
+        match self {
+            Error::Parse(err) => Some(err.span.clone()),
+            Error::Eval(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Parse(err) => write!(f, "{}", err),
+            Error::Eval(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(err: ParseError) -> Self {
+        Error::Parse(err)
+    }
+}
+
+impl From<EvalError> for Error {
+    fn from(err: EvalError) -> Self {
+        Error::Eval(err)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LineError {
+    /// 1-based line number
+    pub line: usize,
+    pub text: String,
+    pub error: Error,
+}
+
+/// The message followed by the line with carets under the offending span.
+impl fmt::Display for LineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "line {}: {}", self.line, self.error)?;
+        write!(f, "    {}", self.text)?;
+        if let Some(span) = self.error.span() {
+            write!(f, "\n    {}", underline(&self.text, span))?;
+        }
+        Ok(())
+    }
+}
+
+/// Carets under `span` of `text`, at least one even for an empty span.
+pub fn underline(text: &str, span: Range<usize>) -> String {
+    let start = text[..span.start].chars().count();
+    let width = text[span].chars().count().max(1);
+    format!("{}{}", " ".repeat(start), "^".repeat(width))
+}
+
+/// Precedence climbing parser over the lexed tokens.
+struct Parser<'a> {
+    line: &'a str,
+    tokens: Vec<(Token, Range<usize>)>,
+    pos: usize,
+    precedence: &'a Precedence,
+}
+
+impl<'a> Parser<'a> {
+    fn parse(line: &'a str, precedence: &'a Precedence) -> Result<Expr, ParseError> {
+        let mut parser = Self {
+            line,
+            tokens: Token::lexer(line).spanned().collect(),
+            pos: 0,
+            precedence,
+        };
+        let expr = parser.parse_expr(0)?;
+        match parser.peek() {
+            None => Ok(expr),
+            Some(Token::Close) => Err(parser.error(ParseErrorKind::UnmatchedParen)),
+            Some(token) => Err(parser.error(ParseErrorKind::UnexpectedToken(token))),
+        }
+    }
+
+    fn peek(&self) -> Option<Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token.clone())
+    }
+
+    /// Error at the current token, or just past the last one.
+    fn error(&self, kind: ParseErrorKind) -> ParseError {
+        let span = match self.tokens.get(self.pos) {
+            Some((_, span)) => span.clone(),
+            None => {
+                let end = self.tokens.last().map_or(0, |(_, span)| span.end);
+                end..end
+            }
+        };
+        let kind = match (kind, self.peek()) {
+            (ParseErrorKind::UnexpectedToken(_), Some(Token::Error)) => {
+                let c = self.line[span.start..].chars().next().unwrap_or_default();
+                ParseErrorKind::InvalidChar(c)
+            }
+            (kind, _) => kind,
+        };
+        ParseError { kind, span }
+    }
+
+    /// `min_level` is wider than the levels so the one above the highest still fits.
+    fn parse_expr(&mut self, min_level: u16) -> Result<Expr, ParseError> {
+        let mut lhs = self.parse_unary()?;
+        while let Some(op) = self.peek().as_ref().and_then(BinOp::from_token) {
+            let (level, assoc) = self
+                .precedence
+                .get(op)
+                .ok_or_else(|| self.error(ParseErrorKind::UnknownOperator(op)))?;
+            let level = u16::from(level);
+            if level < min_level {
+                break;
+            }
+            self.pos += 1;
+            let next_min_level = match assoc {
+                Assoc::Left => level + 1,
+                Assoc::Right => level,
+            };
+            let rhs = self.parse_expr(next_min_level)?;
+            lhs = Expr::BinOp(Box::new(lhs), op, Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, ParseError> {
+        if self.peek() == Some(Token::Sub) {
+            self.pos += 1;
+            let operand = self.parse_expr(self.precedence.unary)?;
+            Ok(Expr::Neg(Box::new(operand)))
+        } else {
+            self.parse_primary()
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, ParseError> {
+        let token = match self.peek() {
+            Some(token) => token,
+            None => return Err(self.error(ParseErrorKind::UnexpectedEnd)),
+        };
+        match token {
+            Token::Num(digits) => {
+                self.pos += 1;
+                Ok(Expr::Num(digits))
+            }
+            Token::Ref(n) => {
+                self.pos += 1;
+                Ok(Expr::Ref(n))
+            }
+            Token::Open => {
+                let open = self.tokens[self.pos].1.clone();
+                self.pos += 1;
+                let expr = self.parse_expr(0)?;
+                match self.peek() {
+                    Some(Token::Close) => {
+                        self.pos += 1;
+                        Ok(expr)
+                    }
+                    Some(token) => Err(self.error(ParseErrorKind::UnexpectedToken(token))),
+                    None => Err(ParseError {
+                        kind: ParseErrorKind::UnclosedParen {
+                            column: self.line[..open.start].chars().count() + 1,
+                        },
+                        span: open,
+                    }),
+                }
+            }
+            _ => Err(self.error(ParseErrorKind::UnexpectedToken(token))),
+        }
+    }
+}
+
+pub fn parse(line: &str, precedence: &Precedence) -> Result<Expr, ParseError> {
+    Parser::parse(line, precedence)
+}
+
+pub fn eval<T: Arith>(line: &str, precedence: &Precedence) -> Result<T, Error> {
+    Ok(parse(line, precedence)?.eval()?)
+}
+
+/// Adds up every line, an overflowing sum is blamed on the line that caused it.
+pub fn sum_lines<T: Arith>(input: &str, precedence: &Precedence) -> Result<T, LineError> {
+    let mut sum = T::from_digits("0").expect("zero should fit");
+    for (idx, line) in input.lines().enumerate() {
+        let line_error = |error| LineError {
+            line: idx + 1,
+            text: line.to_owned(),
+            error,
+        };
+        let value = eval(line, precedence).map_err(line_error)?;
+        sum = T::apply(BinOp::Add, sum, value).map_err(|err| line_error(err.into()))?;
+    }
+    Ok(sum)
+}
+
+/// Every line that fails to parse or evaluate.
+pub fn check_lines<T: Arith>(input: &str, precedence: &Precedence) -> Vec<LineError> {
+    input
+        .lines()
+        .enumerate()
+        .filter_map(|(idx, line)| {
+            let error = eval::<T>(line, precedence).err()?;
+            Some(LineError {
+                line: idx + 1,
+                text: line.to_owned(),
+                error,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(line: &str, precedence: &Precedence) -> Result<i64, Error> {
+        super::eval(line, precedence)
+    }
+
+    #[test]
+    fn test_part1_samples() {
+        let precedence = Precedence::same();
+        assert_eq!(eval("1 + 2 * 3 + 4 * 5 + 6", &precedence), Ok(71));
+        assert_eq!(eval("1 + (2 * 3) + (4 * (5 + 6))", &precedence), Ok(51));
+        assert_eq!(eval("2 * 3 + (4 * 5)", &precedence), Ok(26));
+        assert_eq!(
+            eval(
+                "((2 + 4 * 9) * (6 + 9 * 8 + 6) + 6) + 2 + 4 * 2",
+                &precedence
+            ),
+            Ok(13632)
+        );
+    }
+
+    #[test]
+    fn test_part2_samples() {
+        let precedence = Precedence::add_before_mul();
+        assert_eq!(eval("1 + (2 * 3) + (4 * (5 + 6))", &precedence), Ok(51));
+        assert_eq!(eval("2 * 3 + (4 * 5)", &precedence), Ok(46));
+        assert_eq!(eval("5 + (8 * 3 + 9 + 3 * 4 * 3)", &precedence), Ok(1445));
+        assert_eq!(
+            eval("5 * 9 * (7 * 3 * 3 + 9 * 3 + (8 + 6 * 4))", &precedence),
+            Ok(669060)
+        );
+        assert_eq!(
+            eval(
+                "((2 + 4 * 9) * (6 + 9 * 8 + 6) + 6) + 2 + 4 * 2",
+                &precedence
+            ),
+            Ok(23340)
+        );
+    }
+
+    #[test]
+    fn test_math_precedence() {
+        assert_eq!(eval("1 + 2 * 3 + 4 * 5 + 6", &Precedence::math()), Ok(33));
+        assert_eq!(eval("2 * (3 + 4) * 5", &Precedence::math()), Ok(70));
+    }
+
+    #[test]
+    fn test_precedence_from_str() {
+        assert_eq!("math".parse(), Ok(Precedence::math()));
+        assert_eq!(
+            "+ 2, * 1 left".parse(),
+            Ok(Precedence::new(&[
+                (BinOp::Add, 2, Assoc::Left),
+                (BinOp::Mul, 1, Assoc::Left)
+            ]))
+        );
+        assert_eq!(
+            "+ 1, * 2, ^ 3 right, neg 3"
+                .parse::<Precedence>()
+                .map(|p| p.unary),
+            Ok(3)
+        );
+        assert_eq!(
+            "* 3 right".parse(),
+            Ok(Precedence::new(&[(BinOp::Mul, 3, Assoc::Right)]))
+        );
+        let highest = "+ 255, * 0".parse::<Precedence>().unwrap();
+        assert_eq!(highest.unary, 256);
+        assert_eq!(eval("-1 + 2 * -3 + 4", &highest), Ok(1));
+        assert!("+ 256".parse::<Precedence>().is_err());
+        assert!("& 1".parse::<Precedence>().is_err());
+        assert!("+ 1 up".parse::<Precedence>().is_err());
+    }
+
+    #[test]
+    fn test_highest_level() {
+        let precedence = Precedence {
+            table: HashMap::from([
+                (BinOp::Sub, (u8::MAX, Assoc::Left)),
+                (BinOp::Pow, (u8::MAX, Assoc::Right)),
+            ]),
+            unary: 0,
+        };
+        assert_eq!(eval("5 - 2 - 1", &precedence), Ok(2));
+        assert_eq!(eval("2 ^ 1 ^ 3", &precedence), Ok(2));
+    }
+
+    #[test]
+    fn test_ast() {
+        let line = "1 + 2 * 3";
+        let num = |n: usize| Box::new(Expr::Num(n.to_string()));
+        assert_eq!(
+            Parser::parse(line, &Precedence::math()),
+            Ok(Expr::BinOp(
+                num(1),
+                BinOp::Add,
+                Box::new(Expr::BinOp(num(2), BinOp::Mul, num(3)))
+            ))
+        );
+        let right =
+            Precedence::new(&[(BinOp::Add, 1, Assoc::Right), (BinOp::Mul, 1, Assoc::Right)]);
+        assert_eq!(
+            Parser::parse(line, &right),
+            Ok(Expr::BinOp(
+                num(1),
+                BinOp::Add,
+                Box::new(Expr::BinOp(num(2), BinOp::Mul, num(3)))
+            ))
+        );
+        assert_eq!(
+            Parser::parse(line, &Precedence::same()),
+            Ok(Expr::BinOp(
+                Box::new(Expr::BinOp(num(1), BinOp::Add, num(2))),
+                BinOp::Mul,
+                num(3)
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let precedence = Precedence::same();
+        let parse_err = |kind, span| Err(Error::Parse(ParseError { kind, span }));
+        assert_eq!(
+            eval("3 * (1 + 2", &precedence),
+            parse_err(ParseErrorKind::UnclosedParen { column: 5 }, 4..5)
+        );
+        assert_eq!(
+            eval("1 + 2)", &precedence),
+            parse_err(ParseErrorKind::UnmatchedParen, 5..6)
+        );
+        assert_eq!(
+            eval("1 + * 2", &precedence),
+            parse_err(ParseErrorKind::UnexpectedToken(Token::Mul), 4..5)
+        );
+        assert_eq!(
+            eval("(1 2)", &precedence),
+            parse_err(
+                ParseErrorKind::UnexpectedToken(Token::Num("2".to_owned())),
+                3..4
+            )
+        );
+        assert_eq!(
+            eval("1 +  ", &precedence),
+            parse_err(ParseErrorKind::UnexpectedEnd, 3..3)
+        );
+        assert_eq!(
+            eval("", &precedence),
+            parse_err(ParseErrorKind::UnexpectedEnd, 0..0)
+        );
+        assert_eq!(
+            eval("2 * x", &precedence),
+            parse_err(ParseErrorKind::InvalidChar('x'), 4..5)
+        );
+        let only_add = Precedence::new(&[(BinOp::Add, 1, Assoc::Left)]);
+        assert_eq!(
+            eval("1 * 2", &only_add),
+            parse_err(ParseErrorKind::UnknownOperator(BinOp::Mul), 2..3)
+        );
+    }
+
+    #[test]
+    fn test_error_messages() {
+        let input = "1 + 2\n1 + (2 * 3\n4 / 0\n5 # 6\n(7)";
+        let errors = check_lines::<i64>(input, &Precedence::same());
+        let messages: Vec<String> = errors.iter().map(|err| err.to_string()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "line 2: unclosed '(' opened at column 5\n    1 + (2 * 3\n        ^",
+                "line 3: division by zero\n    4 / 0",
+                "line 4: unexpected character '#'\n    5 # 6\n      ^",
+            ]
+        );
+        assert_eq!(sum_lines::<i64>("1 + 2\n(7)", &Precedence::same()), Ok(10));
+        assert_eq!(
+            sum_lines::<i64>("1\n2 ^ 62\n2 ^ 62", &Precedence::same()).map_err(|err| err.line),
+            Err(3)
+        );
+    }
+
+    #[test]
+    fn test_parenthesized() {
+        let show = |line, precedence: &Precedence| parse(line, precedence).unwrap().to_string();
+        assert_eq!(show("1 + 2 * 3", &Precedence::same()), "((1 + 2) * 3)");
+        assert_eq!(show("1 + 2 * 3", &Precedence::math()), "(1 + (2 * 3))");
+        assert_eq!(
+            show("-2 ^ 2 ^ $1", &Precedence::math()),
+            "(-(2 ^ (2 ^ $1)))"
+        );
+        assert_eq!(show("((7))", &Precedence::math()), "7");
+    }
+
+    #[test]
+    fn test_refs() {
+        let expr = parse("$2 * 10 + $1", &Precedence::math()).unwrap();
+        assert_eq!(expr.eval_with(&[1i64, 2]), Ok(21));
+        assert_eq!(expr.eval_with(&[1i64]), Err(EvalError::UnknownRef(2)));
+        assert_eq!(
+            parse("$0", &Precedence::math()).unwrap().eval::<i64>(),
+            Err(EvalError::UnknownRef(0))
+        );
+    }
+
+    #[test]
+    fn test_extended_operators() {
+        let math = Precedence::math();
+        assert_eq!(eval("10 - 4 - 3", &math), Ok(3));
+        assert_eq!(eval("2 ^ 3 ^ 2", &math), Ok(512));
+        assert_eq!(eval("-2 ^ 2", &math), Ok(-4));
+        assert_eq!(eval("(-2) ^ 2", &math), Ok(4));
+        assert_eq!(eval("7 / 2 + 7 % 2 * 10", &math), Ok(13));
+        assert_eq!(eval("-7 / 2", &math), Ok(-3));
+        assert_eq!(eval("- -3 - -3", &math), Ok(6));
+        assert_eq!(eval("2 * -3", &math), Ok(-6));
+
+        let same = Precedence::same();
+        assert_eq!(eval("1 - 2 * 3 ^ 2", &same), Ok(9));
+        assert_eq!(eval("-1 + 2", &same), Ok(1));
+        assert_eq!(eval("2 ^ 2 - 1 * 3", &Precedence::add_before_mul()), Ok(9));
+    }
+
+    #[test]
+    fn test_checked_arithmetic() {
+        let math = Precedence::math();
+        let eval_err = |err| Err(Error::Eval(err));
+        assert_eq!(
+            eval("1 / (2 - 2)", &math),
+            eval_err(EvalError::DivisionByZero)
+        );
+        assert_eq!(eval("1 % 0", &math), eval_err(EvalError::DivisionByZero));
+        assert_eq!(eval("2 ^ 63", &math), eval_err(EvalError::Overflow));
+        assert_eq!(eval("-2 ^ 63 - 1", &math), eval_err(EvalError::Overflow));
+        assert_eq!(eval("-(2 ^ 62) * 2", &math), Ok(i64::MIN));
+        assert_eq!(eval("2 ^ -1", &math), eval_err(EvalError::NegativeExponent));
+
+        assert_eq!(
+            super::eval::<BigInt>("2 ^ 100 - 1", &math).map(|n| n.to_string()),
+            Ok("1267650600228229401496703205375".to_owned())
+        );
+        assert_eq!(
+            super::eval::<BigInt>("99999999999999999999999 + 1", &math).map(|n| n.to_string()),
+            Ok("100000000000000000000000".to_owned())
+        );
+        assert_eq!(
+            eval("99999999999999999999999 + 1", &math),
+            eval_err(EvalError::LiteralTooLarge)
+        );
+        assert_eq!(
+            eval("9223372036854775808", &math),
+            eval_err(EvalError::LiteralTooLarge)
+        );
+        assert_eq!(eval("9223372036854775807", &math), Ok(i64::MAX));
+        assert_eq!(
+            super::eval::<BigInt>("1 / 0", &math),
+            Err(Error::Eval(EvalError::DivisionByZero))
+        );
+    }
+}
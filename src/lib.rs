@@ -1 +1,2 @@
-
+pub mod crt;
+pub mod homework;